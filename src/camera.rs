@@ -0,0 +1,43 @@
+use crate::{matrix::Matrix, vector::Vector};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vector,
+    pub target: Vector,
+    pub up: Vector,
+    /// 垂直视角, 弧度
+    pub fov: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Vector::new(0.0, 0.0, -20.0),
+            target: Vector::new(0.0, 0.0, 0.0),
+            up: Vector::new(0.0, 1.0, 0.0),
+            fov: 0.8,
+            znear: 0.1,
+            zfar: 1000.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn new(position: Vector, target: Vector) -> Self {
+        Camera {
+            position,
+            target,
+            ..Default::default()
+        }
+    }
+
+    pub fn view_matrix(&self) -> Matrix {
+        Matrix::look_at_lh(&self.position, &self.target, &self.up)
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix {
+        Matrix::perspective_fov_lh(self.fov, aspect, self.znear, self.zfar)
+    }
+}
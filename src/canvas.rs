@@ -1,259 +1,383 @@
 use crate::{
-    color::Color, interpolate::Interpolate, matrix::Matrix, mesh::Mesh, texture::Texture,
-    vector::Vector, vertex::Vertex,
+    camera::Camera,
+    clipper::clip_triangle,
+    color::Color,
+    framebuffer::FrameBuffer,
+    interpolate::Barycentric,
+    light::Light,
+    matrix::Matrix,
+    mesh::Mesh,
+    rasterizer::Triangle,
+    shader::{
+        self, BlinnPhongShader, FragmentShader, GouraudShader, Shaded, Surface, Uniforms, Varying,
+        VertexShader,
+    },
+    shadow::ShadowMap,
+    texture::Texture,
+    tiler::{self, Primitive},
+    vector::Vector,
+    vertex::Vertex,
 };
-use sdl2::render::TextureCreator;
-use sdl2::{
-    pixels::PixelFormatEnum,
-    render::{self, Texture as SdlTexture},
-    surface::Surface,
-    video::{Window, WindowContext},
-    Sdl,
-};
-use std::{mem::swap, time::Duration};
-
-pub struct Canvas<'a> {
-    texture_creator: TextureCreator<WindowContext>,
-    pixels: Surface<'a>,
-    renderer: render::Canvas<Window>,
-    width: u32,
-    height: u32,
-    depth_buffer: Vec<f32>,
+use std::any::Any;
+
+/// 按屏幕上的环绕方向剔除三角形
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+/// 正面三角形在屏幕上 (y 轴向下) 的环绕方向
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrontFace {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// draw_mesh 使用的光照模型
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShadingModel {
+    /// 逐顶点计算漫反射
+    Gouraud,
+    /// 逐片元计算环境光, 漫反射和高光
+    BlinnPhong(BlinnPhongShader),
+}
+
+/// 上次 clear 之后 draw_mesh 处理的三角形数量
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// 提交的三角形
+    pub triangles: usize,
+    /// 完全在视锥体外被裁掉的
+    pub clipped: usize,
+    /// 被背面或正面剔除的
+    pub culled: usize,
+}
+
+pub struct Canvas {
+    framebuffer: FrameBuffer,
+    camera: Camera,
     world_matrix: Matrix,
     view_matrix: Matrix,
-    light: Vector,
+    lights: Vec<Light>,
+    /// 与 lights 一一对应, 由 render_shadows 生成
+    shadow_maps: Vec<Option<ShadowMap>>,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    shading_model: ShadingModel,
+    stats: RenderStats,
+    threads: usize,
+    /// 顶点着色器的输出, 与 Mesh::vertices 一一对应. 类型取决于着色器,
+    /// 着色器不变时每帧重复使用同一块内存
+    vertex_cache: Option<Box<dyn Any + Send>>,
 }
 
-impl<'a> Canvas<'a> {
-    pub fn new(sdl: &Sdl, title: &str, width: u32, height: u32) -> Self {
-        let video_subsystem = sdl.video().unwrap();
-        let window = video_subsystem
-            .window(title, width, height)
-            .position_centered()
-            .allow_highdpi()
-            .build()
-            .unwrap();
-        let renderer = window.into_canvas().build().unwrap();
-        let texture_creator = renderer.texture_creator();
-        let pixels = Surface::new(width, height, PixelFormatEnum::RGBA32).unwrap();
-        let len = (width * height) as usize;
-        let depth_buffer = vec![f32::MAX; len];
-        let light = Vector::new(2.0, 2.0, -15.0);
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        let framebuffer = FrameBuffer::new(width, height);
+        let light = Light::point(Vector::new(2.0, 2.0, -15.0));
         Canvas {
-            pixels,
-            renderer,
-            texture_creator,
-            width,
-            height,
-            depth_buffer,
+            framebuffer,
+            camera: Camera::default(),
             world_matrix: Matrix::zero(),
             view_matrix: Matrix::zero(),
-            light,
+            lights: vec![light],
+            shadow_maps: vec![],
+            cull_mode: CullMode::None,
+            front_face: FrontFace::Clockwise,
+            shading_model: ShadingModel::Gouraud,
+            stats: RenderStats::default(),
+            threads: tiler::default_threads(),
+            vertex_cache: None,
         }
     }
 
-    pub fn render(&mut self) {
-        let Self {
-            pixels,
-            renderer,
-            texture_creator,
-            ..
-        } = self;
-        let texture = SdlTexture::from_surface(pixels, texture_creator).unwrap();
-        renderer.copy(&texture, None, None).unwrap();
-        self.renderer.present()
+    pub fn width(&self) -> u32 {
+        self.framebuffer.width()
     }
 
-    pub fn clear(&mut self) {
-        self.pixels
-            .fill_rect(None, Color::new(0, 0, 0, 255).into())
-            .unwrap();
-        self.depth_buffer.iter_mut().for_each(|x| *x = f32::MAX);
-        self.renderer.clear()
-    }
-
-    fn get_pixel(&self, x: u32, y: u32) -> Color {
-        self.pixels.with_lock(|pixels| {
-            let index = (self.width * y + x) as usize;
-            let i = index * 4;
-            let r = pixels[i] as u8;
-            let g = pixels[i + 1] as u8;
-            let b = pixels[i] as u8;
-            let a = pixels[i] as u8;
-            Color::new(r, g, b, a)
-        })
-    }
-
-    fn set_pixel(&mut self, x: u32, y: u32, z: f32, mut color: Color) {
-        let w = self.width;
-        let index = (w * y + x) as usize;
-
-        let depth = self.depth_buffer[index];
-        if z > depth {
-            return;
-        }
-        if color.a == 0 {
-            return;
-        }
+    pub fn height(&self) -> u32 {
+        self.framebuffer.height()
+    }
 
-        self.depth_buffer[index] = z;
+    pub fn framebuffer(&self) -> &FrameBuffer {
+        &self.framebuffer
+    }
 
-        let bg = self.get_pixel(x, y);
-        color = color.blend_alpha(&bg);
-        self.pixels.with_lock_mut(|pixels| {
-            let index = index * 4;
-            let Color { r, g, b, a } = color;
-            pixels[index] = r;
-            pixels[index + 1] = g;
-            pixels[index + 2] = b;
-            pixels[index + 3] = a;
-        })
+    pub fn camera(&self) -> &Camera {
+        &self.camera
     }
 
-    pub fn draw_point(&mut self, point: &Vector, color: Color) {
-        let x = point.x.round() as u32;
-        let y = point.y.round() as u32;
-        let z = point.w;
-        let Self { width, height, .. } = *self;
-        if x < width && y < height {
-            self.set_pixel(x, y, z, color);
-        }
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
     }
 
-    pub fn draw_scanline<'b>(
-        &mut self,
-        mut v1: &'b Vertex,
-        mut v2: &'b Vertex,
-        texture: Option<&Texture>,
-    ) {
-        if v1.position.x > v2.position.x {
-            swap(&mut v1, &mut v2);
+    /// 世界空间中的光源, 默认是一个白色点光源
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// 原来的阴影贴图随之清除
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.lights = lights;
+        self.shadow_maps.clear();
+    }
+
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+    }
+
+    pub fn cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+    }
+
+    pub fn front_face(&self) -> FrontFace {
+        self.front_face
+    }
+
+    pub fn set_front_face(&mut self, front_face: FrontFace) {
+        self.front_face = front_face;
+    }
+
+    pub fn shading_model(&self) -> ShadingModel {
+        self.shading_model
+    }
+
+    pub fn set_shading_model(&mut self, shading_model: ShadingModel) {
+        self.shading_model = shading_model;
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// draw_mesh 光栅化使用的线程数, 1 表示在当前线程绘制. 结果与线程数无关
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// 清空画面, 同时重置统计
+    pub fn clear(&mut self) {
+        self.framebuffer.clear(Color::new(0, 0, 0, 255));
+        self.stats = RenderStats::default();
+    }
+
+    /// 屏幕上的多边形是否应该剔除, 多边形是平面的, 用整体有向面积判断环绕方向
+    fn is_culled(&self, polygon: &[Vector]) -> bool {
+        let cull_clockwise = match self.cull_mode {
+            CullMode::None => return false,
+            CullMode::Back => self.front_face == FrontFace::CounterClockwise,
+            CullMode::Front => self.front_face == FrontFace::Clockwise,
+        };
+        let mut area = 0.0;
+        for (i, a) in polygon.iter().enumerate() {
+            let b = &polygon[(i + 1) % polygon.len()];
+            area += a.x * b.y - b.x * a.y;
         }
-        let x1 = v1.position.x;
-        let x2 = v2.position.x;
-        let (start, end) = (x1 as u32, x2 as u32);
-        for x in start..=end {
-            let factor = if start == end {
-                0.0
-            } else {
-                (x as f32 - x1) / (x2 - x1)
-            };
+        // y 轴向下时, 面积为正是顺时针
+        if cull_clockwise {
+            area > 0.0
+        } else {
+            area < 0.0
+        }
+    }
 
-            let v = v1.interpolate(&v2, factor);
-            let mut color = if let Some(t) = texture {
-                t.sample(v.u, v.v)
-            } else {
-                v.color
-            };
-            color = color.shading(v.intensity);
-            self.draw_point(&v.position, color);
+    /// point 的 z 分量为深度, 越小越近
+    pub fn draw_point(&mut self, point: &Vector, color: Color) {
+        let x = point.x.round();
+        let y = point.y.round();
+        if x >= 0.0 && y >= 0.0 && x < self.width() as f32 && y < self.height() as f32 {
+            self.framebuffer
+                .set_pixel(x as u32, y as u32, point.z, color);
         }
     }
 
-    pub fn draw_triangle<'b>(
+    /// 顶点是 project 之后的屏幕坐标
+    pub fn draw_triangle(
         &mut self,
-        mut v1: &'b Vertex,
-        mut v2: &'b Vertex,
-        mut v3: &'b Vertex,
+        v1: &Vertex,
+        v2: &Vertex,
+        v3: &Vertex,
         texture: Option<&Texture>,
     ) {
-        if v1.position.y > v2.position.y {
-            swap(&mut v1, &mut v2);
-        }
-        if v2.position.y > v3.position.y {
-            swap(&mut v2, &mut v3);
-        }
-        if v1.position.y > v2.position.y {
-            swap(&mut v1, &mut v2);
-        }
-        let middle_factor = (v2.position.y - v1.position.y) / (v3.position.y - v1.position.y);
-        let middle = v1.interpolate(v3, middle_factor);
-        // assert_eq!(middle.position.y, v2.position.y);
-        // println!("middle {:?}", middle);
-        let start_y = v1.position.y as i32;
-        let end_y = v2.position.y.ceil() as i32;
-        for y in start_y..=end_y {
-            let factor = if start_y == end_y {
-                0.0
-            } else {
-                (y - start_y) as f32 / (end_y - start_y) as f32
+        if let Some(triangle) = Triangle::new(&v1.position, &v2.position, &v3.position) {
+            let primitive = Primitive {
+                triangle,
+                vertices: [*v1, *v2, *v3],
+                material: None,
             };
-            let va = v1.interpolate(v2, factor);
-            let vb = v1.interpolate(&middle, factor);
-            self.draw_scanline(&va, &vb, texture);
-        }
-        let start_y = v2.position.y as i32;
-        let end_y = v3.position.y.ceil() as i32;
-        for y in start_y..=end_y {
-            let factor = if start_y == end_y {
-                0.0
-            } else {
-                (y - start_y) as f32 / (end_y - start_y) as f32
+            let surface = Surface {
+                material: None,
+                texture,
             };
-            let va = v2.interpolate(v3, factor);
-            let vb = middle.interpolate(v3, factor);
-            self.draw_scanline(&va, &vb, texture);
+            tiler::draw_primitives(&mut self.framebuffer, &[primitive], 1, |p, f| {
+                let [a, b, c] = &p.vertices;
+                let v = Vertex::barycentric(a, b, c, f.weights);
+                surface.albedo(v.u, v.v, v.color).shading(v.intensity)
+            });
         }
     }
 
+    /// 变换到裁剪空间并映射到屏幕, 没有经过裁剪, 只适用于确定在相机前面的顶点
     pub fn project(&self, v: &Vertex, transform: &Matrix) -> Vertex {
-        let mut p = transform.transform(&v.position);
-        let w = self.width as f32;
-        let h = self.height as f32;
-        p.x = p.x * w + w / 2.0;
-        p.y = -p.y * h + h / 2.0;
+        let position = transform.transform_homogeneous(&v.position);
+        self.to_screen(&Vertex { position, ..*v })
+    }
 
-        Vertex { position: p, ..*v }
+    /// 裁剪空间的顶点做透视除法后映射到屏幕, position.w 保存 1 / w
+    pub fn to_screen(&self, v: &Vertex) -> Vertex {
+        let position = self.screen_position(&v.position);
+        Vertex { position, ..*v }
     }
 
+    fn screen_position(&self, clip: &Vector) -> Vector {
+        screen_position(clip, self.width() as f32, self.height() as f32)
+    }
+
+    /// 按 shading_model 选择的光照模型绘制
     pub fn draw_mesh(&mut self, mesh: &mut Mesh) {
-        let camera_position = Vector::new(0.0, 0.0, -20.0);
-        let camera_target = Vector::new(0.0, 0.0, 0.0);
-        let camera_up = Vector::new(0.0, 1.0, 0.0);
-
-        let view = Matrix::look_at_lh(&camera_position, &camera_target, &camera_up);
-        let projection =
-            Matrix::perspective_fov_lh(0.8, self.width as f32 / self.height as f32, 0.1, 1.0);
-        let rotation = Matrix::rotation(&mesh.rotation);
-        let translation = Matrix::translation(&mesh.position);
-
-        let world = rotation * translation;
-        let transform = world * view * projection;
+        match self.shading_model {
+            ShadingModel::Gouraud => self.draw_mesh_with(mesh, &GouraudShader, &GouraudShader),
+            ShadingModel::BlinnPhong(shader) => self.draw_mesh_with(mesh, &shader, &shader),
+        }
+    }
+
+    /// 每个顶点运行一次顶点着色器, 裁剪和剔除之后光栅化, 每个片元运行一次片元着色器
+    pub fn draw_mesh_with<VS, FS>(&mut self, mesh: &Mesh, vertex_shader: &VS, fragment_shader: &FS)
+    where
+        VS: VertexShader,
+        FS: FragmentShader<VS::Varying>,
+    {
+        let world = mesh.world_matrix();
+        // 绘制过程中还要修改 self, 先把光源和阴影贴图取出来, 画完再放回去
+        let lights = std::mem::take(&mut self.lights);
+        let shadow_maps = std::mem::take(&mut self.shadow_maps);
+        let uniforms = self.uniforms(world, &lights, &shadow_maps);
         self.world_matrix = world;
-        self.view_matrix = view;
-
-        for (i, j, k) in &mesh.indices {
-            let mut a = mesh.vertices[*i];
-            let mut b = mesh.vertices[*j];
-            let mut c = mesh.vertices[*k];
-            self.shading(&mut a, &mut b, &mut c);
-            // println!("draw triangle {:?} {:?} {:?}", a, b, c);
-            // println!("{:?} {:?} {:?}", a, b, c);
-            let v1 = self.project(&a, &transform);
-            let v2 = self.project(&b, &transform);
-            let v3 = self.project(&c, &transform);
-            // println!("{:?} {:?} {:?}", v1, v2, v3);
-            self.draw_triangle(&v1, &v2, &v3, mesh.texture.as_ref());
+        self.view_matrix = uniforms.view;
+
+        // 顶点处理: 每个顶点只运行一次顶点着色器, 三角形共用的顶点不重复计算
+        let mut cache = self.take_vertex_cache::<VS::Varying>();
+        cache.extend(mesh.vertices.iter().map(|v| {
+            let (position, varying) = vertex_shader.vertex(&uniforms, v);
+            Shaded { position, varying }
+        }));
+
+        let mut primitives = vec![];
+        for submesh in &mesh.submeshes {
+            for &(i, j, k) in &mesh.indices[submesh.indices.clone()] {
+                self.stats.triangles += 1;
+                let polygon = clip_triangle(&cache[i], &cache[j], &cache[k]);
+                if polygon.is_empty() {
+                    self.stats.clipped += 1;
+                    continue;
+                }
+                let screen: Vec<Vector> = polygon
+                    .iter()
+                    .map(|v| self.screen_position(&v.position))
+                    .collect();
+                if self.is_culled(&screen) {
+                    self.stats.culled += 1;
+                    continue;
+                }
+                for i in 1..polygon.len() - 1 {
+                    if let Some(triangle) = Triangle::new(&screen[0], &screen[i], &screen[i + 1]) {
+                        primitives.push(Primitive {
+                            triangle,
+                            vertices: [polygon[0], polygon[i], polygon[i + 1]].map(|v| v.varying),
+                            material: submesh.material,
+                        });
+                    }
+                }
+            }
         }
+
+        self.vertex_cache = Some(Box::new(cache));
+
+        tiler::draw_primitives(&mut self.framebuffer, &primitives, self.threads, |p, f| {
+            let material = p.material.map(|i| &mesh.materials[i]);
+            let texture = material
+                .and_then(|m| m.texture.as_ref())
+                .or(mesh.texture.as_ref());
+            let surface = Surface { material, texture };
+            let [a, b, c] = &p.vertices;
+            let varying = VS::Varying::barycentric(a, b, c, f.weights);
+            fragment_shader.fragment(&uniforms, &surface, &varying)
+        });
+        self.lights = lights;
+        self.shadow_maps = shadow_maps;
     }
 
-    pub fn shading(&self, v1: &mut Vertex, v2: &mut Vertex, v3: &mut Vertex) {
-        for v in vec![v1, v2, v3] {
-            self.gouraud_shading(v);
+    fn uniforms<'a>(
+        &self,
+        world: Matrix,
+        lights: &'a [Light],
+        shadows: &'a [Option<ShadowMap>],
+    ) -> Uniforms<'a> {
+        let view = self.camera.view_matrix();
+        let projection = self
+            .camera
+            .projection_matrix(self.width() as f32 / self.height() as f32);
+        Uniforms {
+            world,
+            view,
+            projection,
+            transform: world * view * projection,
+            lights,
+            shadows,
+            camera: view.eye_position(),
         }
     }
-    pub fn gouraud_shading(&self, v: &mut Vertex) {
-        let light = &self.light;
-        let world = self.world_matrix;
 
-        let n = world.transform_vector(&v.normal).normalize();
-        let p = (light - &self.world_matrix.transform_vector(&v.position)).normalize();
+    /// 从每个投射阴影的光源渲染 meshes 的阴影贴图, 之后绘制的模型都会使用.
+    /// 模型, 光源移动之后要重新渲染
+    pub fn render_shadows(&mut self, meshes: &[&Mesh]) {
+        self.shadow_maps = self
+            .lights
+            .iter()
+            .map(|light| {
+                let options = light.shadow.as_ref()?;
+                ShadowMap::render(light, meshes, options)
+            })
+            .collect();
+    }
+
+    /// 是否有光源投射阴影
+    pub fn casts_shadows(&self) -> bool {
+        self.lights.iter().any(|light| light.shadow.is_some())
+    }
+
+    /// 上次绘制使用的顶点缓存, 类型不同时重新分配
+    fn take_vertex_cache<V: Varying>(&mut self) -> Vec<Shaded<V>> {
+        match self.vertex_cache.take().map(|cache| cache.downcast()) {
+            Some(Ok(cache)) => {
+                let mut cache: Vec<Shaded<V>> = *cache;
+                cache.clear();
+                cache
+            }
+            _ => vec![],
+        }
+    }
 
-        let mut intensity = n.dot(&p);
-        if intensity < 0.0 {
-            intensity = 0.0;
+    pub fn shading(&self, v1: &mut Vertex, v2: &mut Vertex, v3: &mut Vertex) {
+        for v in [v1, v2, v3] {
+            self.gouraud_shading(v);
         }
-        v.intensity = intensity;
+    }
+    /// 顶点只有一个光照强度, 彩色光只保留最亮的分量
+    pub fn gouraud_shading(&self, v: &mut Vertex) {
+        let uniforms = self.uniforms(self.world_matrix, &self.lights, &self.shadow_maps);
+        let light = shader::gouraud_lighting(&uniforms, v);
+        v.intensity = light.x.max(light.y).max(light.z);
     }
 
     pub fn draw_image(&mut self, image: &Texture) {
@@ -271,3 +395,14 @@ impl<'a> Canvas<'a> {
         }
     }
 }
+
+/// 裁剪空间坐标映射到 width x height 的屏幕, position.w 保存 1 / w
+pub(crate) fn screen_position(clip: &Vector, width: f32, height: f32) -> Vector {
+    let Vector { x, y, z, w } = *clip;
+    Vector {
+        x: x / w * width + width / 2.0,
+        y: -y / w * height + height / 2.0,
+        z: z / w,
+        w: 1.0 / w,
+    }
+}
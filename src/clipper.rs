@@ -0,0 +1,144 @@
+//! 齐次裁剪空间中的 Sutherland–Hodgman 三角形裁剪.
+//!
+//! 投影矩阵是 D3D 风格的左手系, 可见范围为 -w <= x <= w, -w <= y <= w, 0 <= z <= w.
+//! 裁剪在透视除法之前进行, 这样穿过近平面或在相机后面的三角形不会在除以 w 时翻转.
+use crate::{interpolate::Interpolate, vector::Vector, vertex::Vertex};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Plane {
+    Near,
+    Far,
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl Plane {
+    pub const ALL: [Plane; 6] = [
+        Plane::Near,
+        Plane::Far,
+        Plane::Left,
+        Plane::Right,
+        Plane::Bottom,
+        Plane::Top,
+    ];
+
+    /// 点到平面的有向距离, 非负时在可见的一侧
+    pub fn distance(&self, p: &Vector) -> f32 {
+        match self {
+            Plane::Near => p.z,
+            Plane::Far => p.w - p.z,
+            Plane::Left => p.w + p.x,
+            Plane::Right => p.w - p.x,
+            Plane::Bottom => p.w + p.y,
+            Plane::Top => p.w - p.y,
+        }
+    }
+}
+
+/// 可以裁剪的顶点: 有裁剪空间坐标, 其他属性可以线性插值
+pub trait ClipVertex: Interpolate + Copy {
+    fn clip_position(&self) -> &Vector;
+}
+
+impl ClipVertex for Vertex {
+    fn clip_position(&self) -> &Vector {
+        &self.position
+    }
+}
+
+/// 裁剪一个顶点位于裁剪空间的三角形, 返回凸多边形的顶点, 完全不可见时为空.
+/// 新顶点的所有属性都在裁剪空间中线性插值
+pub fn clip_triangle<V: ClipVertex>(a: &V, b: &V, c: &V) -> Vec<V> {
+    let vertices = [a, b, c];
+    let mut inside_all = true;
+    for plane in &Plane::ALL {
+        let outside = vertices
+            .iter()
+            .filter(|v| plane.distance(v.clip_position()) < 0.0)
+            .count();
+        if outside == 3 {
+            return vec![];
+        }
+        inside_all &= outside == 0;
+    }
+    if inside_all {
+        return vec![*a, *b, *c];
+    }
+
+    let mut polygon = vec![*a, *b, *c];
+    for plane in &Plane::ALL {
+        polygon = clip_polygon(&polygon, *plane);
+        if polygon.len() < 3 {
+            return vec![];
+        }
+    }
+    polygon
+}
+
+fn clip_polygon<V: ClipVertex>(polygon: &[V], plane: Plane) -> Vec<V> {
+    let mut out = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate() {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let d1 = plane.distance(current.clip_position());
+        let d2 = plane.distance(next.clip_position());
+        if d1 >= 0.0 {
+            out.push(*current);
+        }
+        if (d1 >= 0.0) != (d2 >= 0.0) {
+            out.push(current.interpolate(next, d1 / (d1 - d2)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn vertex(x: f32, y: f32, z: f32, w: f32, u: f32) -> Vertex {
+        let mut v = Vertex::new(
+            Vector::new(x, y, z),
+            Vector::default(),
+            u,
+            0.0,
+            Color::default(),
+        );
+        v.position.w = w;
+        v
+    }
+
+    #[test]
+    fn test_inside() {
+        let a = vertex(0.0, 0.0, 0.5, 1.0, 0.0);
+        let b = vertex(0.5, 0.0, 0.5, 1.0, 0.0);
+        let c = vertex(0.0, 0.5, 0.5, 1.0, 0.0);
+        assert_eq!(clip_triangle(&a, &b, &c), vec![a, b, c]);
+    }
+
+    #[test]
+    fn test_behind_camera() {
+        let a = vertex(0.0, 0.0, -0.5, -1.0, 0.0);
+        let b = vertex(0.5, 0.0, -0.5, -1.0, 0.0);
+        let c = vertex(0.0, 0.5, -0.5, -1.0, 0.0);
+        assert!(clip_triangle(&a, &b, &c).is_empty());
+    }
+
+    #[test]
+    fn test_near_plane() {
+        // 一个顶点在近平面后面, 裁剪后是四边形, 新顶点在近平面上且属性按比例插值
+        let a = vertex(0.0, 0.0, -1.0, 1.0, 0.0);
+        let b = vertex(0.5, 0.0, 1.0, 2.0, 1.0);
+        let c = vertex(0.0, 0.5, 1.0, 2.0, 1.0);
+        let polygon = clip_triangle(&a, &b, &c);
+        assert_eq!(polygon.len(), 4);
+        for v in &polygon {
+            assert!(v.position.z >= 0.0);
+        }
+        let on_plane: Vec<&Vertex> = polygon.iter().filter(|v| v.position.z == 0.0).collect();
+        assert_eq!(on_plane.len(), 2);
+        assert!(on_plane.iter().all(|v| v.u == 0.5 && v.position.w == 1.5));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::vector::Vector;
+#[cfg(feature = "window")]
 use sdl2::pixels::Color as SdlColor;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
@@ -13,6 +15,27 @@ impl Color {
         Color { r, g, b, a }
     }
 
+    /// 打包成 u32, 按小端序存放时字节顺序为 RGBA
+    pub fn to_u32(self) -> u32 {
+        u32::from_le_bytes([self.r, self.g, self.b, self.a])
+    }
+
+    pub fn from_u32(value: u32) -> Color {
+        let [r, g, b, a] = value.to_le_bytes();
+        Color { r, g, b, a }
+    }
+
+    /// r, g, b 分别乘以 k 的 x, y, z 分量, 透明度不变
+    pub fn tint(&self, k: &Vector) -> Color {
+        let scale = |c: u8, k: f32| (c as f32 * k) as u8;
+        Color {
+            r: scale(self.r, k.x),
+            g: scale(self.g, k.y),
+            b: scale(self.b, k.z),
+            a: self.a,
+        }
+    }
+
     pub fn shading(&self, intensity: f32) -> Color {
         let Self { r, g, b, a } = *self;
         let r = r as f32 * intensity;
@@ -40,9 +63,10 @@ impl Color {
     }
 }
 
-impl Into<SdlColor> for Color {
-    fn into(self) -> SdlColor {
-        let Color { r, g, b, a } = self;
+#[cfg(feature = "window")]
+impl From<Color> for SdlColor {
+    fn from(color: Color) -> SdlColor {
+        let Color { r, g, b, a } = color;
         SdlColor::RGBA(r, g, b, a)
     }
 }
@@ -0,0 +1,169 @@
+use crate::{
+    color::Color,
+    image::{gif::GifEncoder, png},
+    rasterizer::Rect,
+};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// 内存中的帧缓冲, 保存颜色和深度, 不依赖任何窗口系统.
+/// 颜色用 Color::to_u32 打包存放, 每个像素 4 字节
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+    depth_buffer: Vec<f32>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![Color::new(0, 0, 0, 255).to_u32(); len],
+            depth_buffer: vec![f32::MAX; len],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn depth_buffer(&self) -> &[f32] {
+        &self.depth_buffer
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let color = color.to_u32();
+        self.pixels.iter_mut().for_each(|x| *x = color);
+        self.depth_buffer.iter_mut().for_each(|x| *x = f32::MAX);
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        let index = (self.width * y + x) as usize;
+        Color::from_u32(self.pixels[index])
+    }
+
+    /// 深度测试通过后, 与背景做 alpha 混合再写入
+    pub fn set_pixel(&mut self, x: u32, y: u32, z: f32, color: Color) {
+        let index = (self.width * y + x) as usize;
+        write_pixel(
+            &mut self.pixels[index],
+            &mut self.depth_buffer[index],
+            z,
+            color,
+        );
+    }
+
+    /// 按每块 rows 行切分成互不重叠的图块, 各个图块可以交给不同线程同时写入.
+    /// 宽或高为 0 时没有图块
+    pub fn tiles_mut(&mut self, rows: u32) -> Vec<Tile<'_>> {
+        let width = self.width;
+        let height = self.height;
+        let rows = rows.max(1);
+        let len = width as usize * rows as usize;
+        if len == 0 {
+            return vec![];
+        }
+        self.pixels
+            .chunks_mut(len)
+            .zip(self.depth_buffer.chunks_mut(len))
+            .enumerate()
+            .map(|(i, (pixels, depth_buffer))| {
+                let y0 = i as u32 * rows;
+                Tile {
+                    rect: Rect::new(0, y0, width, (y0 + rows).min(height)),
+                    pixels,
+                    depth_buffer,
+                }
+            })
+            .collect()
+    }
+
+    /// 按 RGBA 字节顺序导出像素, 用于上传到窗口或写入图片
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.pixels.len() * 4];
+        self.write_rgba_bytes(&mut bytes, self.width as usize * 4);
+        bytes
+    }
+
+    /// 按 RGBA 字节顺序写入 out, pitch 为 out 中每行的字节数
+    pub fn write_rgba_bytes(&self, out: &mut [u8], pitch: usize) {
+        let width = self.width as usize;
+        for (row, pixels) in out.chunks_mut(pitch).zip(self.pixels.chunks(width)) {
+            for (bytes, pixel) in row.chunks_exact_mut(4).zip(pixels) {
+                bytes.copy_from_slice(&pixel.to_le_bytes());
+            }
+        }
+    }
+
+    /// 根据扩展名保存为 png 或单帧 gif
+    pub fn save(&self, path: &str) -> Result<()> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => self.save_png(path),
+            Some("gif") => self.save_gif(path),
+            _ => bail!("不支持的图片格式: {}", path),
+        }
+    }
+
+    pub fn save_png(&self, path: &str) -> Result<()> {
+        png::save(path, self.width, self.height, &self.to_rgba_bytes())
+    }
+
+    pub fn save_gif(&self, path: &str) -> Result<()> {
+        let mut encoder = GifEncoder::new(self.width, self.height, None)?;
+        encoder.add_frame(&self.to_rgba_bytes(), 0)?;
+        encoder.save(path)
+    }
+}
+
+/// 帧缓冲中连续若干整行组成的图块, 独占这些行的颜色和深度
+pub struct Tile<'a> {
+    rect: Rect,
+    pixels: &'a mut [u32],
+    depth_buffer: &'a mut [f32],
+}
+
+impl Tile<'_> {
+    /// 图块在帧缓冲中的范围
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// 坐标是整个帧缓冲中的坐标, 必须落在 rect 内
+    pub fn set_pixel(&mut self, x: u32, y: u32, z: f32, color: Color) {
+        let index = ((y - self.rect.y0) * self.rect.x1 + x) as usize;
+        write_pixel(
+            &mut self.pixels[index],
+            &mut self.depth_buffer[index],
+            z,
+            color,
+        );
+    }
+}
+
+/// 深度测试通过后, 读出原来的颜色混合再写回
+fn write_pixel(pixel: &mut u32, depth: &mut f32, z: f32, color: Color) {
+    if z > *depth || color.a == 0 {
+        return;
+    }
+    *depth = z;
+    *pixel = if color.a == 255 {
+        color.to_u32()
+    } else {
+        color.blend_alpha(&Color::from_u32(*pixel)).to_u32()
+    };
+}
@@ -0,0 +1,53 @@
+//! 图片编解码, 全部在 crate 内实现, 不依赖外部库
+mod bits;
+pub mod bmp;
+pub mod gif;
+pub mod png;
+pub mod ppm;
+pub mod tga;
+mod zlib;
+
+use anyhow::{anyhow, bail, Result};
+use std::fs;
+use std::path::Path;
+
+/// 从上到下逐行存放的 8 位 RGBA 像素
+#[derive(Clone, Debug, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// 把文件头中的各个尺寸相乘得到字节数, 溢出时报错. 尺寸来自文件头, 不可信,
+/// 解码器要先用它确认剩下的数据足够, 再按它分配像素
+pub(crate) fn checked_size(format: &str, dims: &[usize]) -> Result<usize> {
+    dims.iter()
+        .try_fold(1usize, |size, &n| size.checked_mul(n))
+        .ok_or_else(|| anyhow!("{} 尺寸过大", format))
+}
+
+/// 读取 png, bmp, tga 或二进制 ppm / pgm 图片
+pub fn load(path: &str) -> Result<RgbaImage> {
+    let content = fs::read(path)?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    decode(&content, extension.as_deref())
+}
+
+/// 按文件开头的标识选择格式, tga 没有标识, 只能按扩展名判断
+pub fn decode(content: &[u8], extension: Option<&str>) -> Result<RgbaImage> {
+    if content.starts_with(b"\x89PNG") {
+        png::decode(content)
+    } else if content.starts_with(b"BM") {
+        bmp::decode(content)
+    } else if content.starts_with(b"P5") || content.starts_with(b"P6") {
+        ppm::decode(content)
+    } else if extension == Some("tga") {
+        tga::decode(content)
+    } else {
+        bail!("不支持的图片格式")
+    }
+}
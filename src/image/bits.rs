@@ -0,0 +1,83 @@
+/// 低位优先的位流, deflate 和 gif 的 LZW 都使用这种顺序
+#[derive(Default)]
+pub struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 写入 value 的低 n 位, n 不超过 24
+    pub fn write_bits(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// 低位优先读取的位流
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// 读取 n 位, n 不超过 24, 数据不足时返回 None
+    pub fn read_bits(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.position)?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Some(value)
+    }
+
+    /// 丢弃当前字节剩余的位
+    pub fn align(&mut self) {
+        let rest = self.count % 8;
+        self.buffer >>= rest;
+        self.count -= rest;
+    }
+
+    /// 对齐后按字节读取
+    pub fn read_bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        self.align();
+        // 先退回缓冲中还没用掉的整字节
+        self.position -= (self.count / 8) as usize;
+        self.buffer = 0;
+        self.count = 0;
+        let bytes = self.data.get(self.position..self.position + n)?;
+        self.position += n;
+        Some(bytes)
+    }
+}
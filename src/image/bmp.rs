@@ -0,0 +1,160 @@
+//! BMP 解码, 支持不压缩或按位域存放的 24 位和 32 位图片
+use super::{checked_size, RgbaImage};
+use anyhow::{anyhow, bail, Result};
+
+pub fn decode(content: &[u8]) -> Result<RgbaImage> {
+    if !content.starts_with(b"BM") {
+        bail!("不是 bmp 文件");
+    }
+    let u16_at = |i: usize| {
+        content
+            .get(i..i + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |i: usize| {
+        content
+            .get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let eof = || anyhow!("bmp 数据不完整");
+    let offset = u32_at(10).ok_or_else(eof)? as usize;
+    let header_size = u32_at(14).ok_or_else(eof)? as usize;
+    if header_size < 40 {
+        bail!("不支持的 bmp 头部长度 {}", header_size);
+    }
+    let width = u32_at(18).ok_or_else(eof)? as i32;
+    let height = u32_at(22).ok_or_else(eof)? as i32;
+    let depth = u16_at(28).ok_or_else(eof)?;
+    let compression = u32_at(30).ok_or_else(eof)?;
+    if width <= 0 || height == 0 {
+        bail!("bmp 尺寸错误 {}x{}", width, height);
+    }
+    // 高度为负数时从上到下存放
+    let top_down = height < 0;
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+
+    let masks = match (depth, compression) {
+        (24, 0) | (32, 0) => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000],
+        // BI_BITFIELDS / BI_ALPHABITFIELDS, 掩码紧跟在 40 字节的头部后面
+        (32, 3) | (32, 6) => {
+            let mask = |i: usize| u32_at(54 + i * 4).ok_or_else(eof);
+            let alpha = if compression == 6 || header_size >= 56 {
+                mask(3)?
+            } else {
+                0
+            };
+            [mask(0)?, mask(1)?, mask(2)?, alpha]
+        }
+        _ => bail!(
+            "只支持 24 位和 32 位的 bmp, 当前 {} 位, 压缩方式 {}",
+            depth,
+            compression
+        ),
+    };
+
+    let bytes = depth as usize / 8;
+    // 每行补齐到 4 字节
+    let stride = (width * bytes).div_ceil(4) * 4;
+    let size = checked_size("bmp", &[stride, height])?;
+    let data = content
+        .get(offset..)
+        .and_then(|data| data.get(..size))
+        .ok_or_else(eof)?;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[row * stride..row * stride + width * bytes];
+        for p in row.chunks_exact(bytes) {
+            // 24 位没有透明度, 补上不透明的第 4 个字节
+            let value = match *p {
+                [b, g, r] => u32::from_le_bytes([b, g, r, 0xff]),
+                [b0, b1, b2, b3] => u32::from_le_bytes([b0, b1, b2, b3]),
+                _ => unreachable!(),
+            };
+            pixels.extend(masks.iter().map(|&mask| channel(value, mask)));
+        }
+    }
+    // 32 位 bmp 的第 4 个字节经常不用, 全为 0 时当作不透明
+    if depth == 32 && pixels.chunks(4).all(|p| p[3] == 0) {
+        for p in pixels.chunks_mut(4) {
+            p[3] = 255;
+        }
+    }
+    Ok(RgbaImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// 按掩码取出一个分量并扩展到 8 位, 没有掩码的分量是 0
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones();
+    let v = (value & mask) >> mask.trailing_zeros();
+    let max = if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    (v as u64 * 255 / max as u64) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bmp(depth: u16, height: i32, compression: u32, masks: &[u32], data: &[u8]) -> Vec<u8> {
+        let offset = 54 + masks.len() as u32 * 4;
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&(offset + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&40u32.to_le_bytes());
+        out.extend_from_slice(&2i32.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&depth.to_le_bytes());
+        out.extend_from_slice(&compression.to_le_bytes());
+        out.extend_from_slice(&[0; 20]);
+        for mask in masks {
+            out.extend_from_slice(&mask.to_le_bytes());
+        }
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_24_bit_bottom_up() {
+        // 每行 6 字节, 补齐到 8 字节. 文件中第一行是图片的最后一行
+        let data = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 1, 2, 3, 0, 0];
+        let image = decode(&bmp(24, 2, 0, &[], &data)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.pixels,
+            vec![0, 0, 255, 255, 3, 2, 1, 255, 255, 0, 0, 255, 0, 255, 0, 255]
+        );
+        assert!(decode(&bmp(24, 2, 0, &[], &data[..12])).is_err());
+
+        // 文件头中的宽和高远超实际数据
+        let mut huge = bmp(24, 2, 0, &[], &data);
+        huge[18..22].copy_from_slice(&i32::MAX.to_le_bytes());
+        huge[22..26].copy_from_slice(&i32::MIN.to_le_bytes());
+        assert!(decode(&huge).is_err());
+    }
+
+    #[test]
+    fn test_32_bit() {
+        let data = [1, 2, 3, 0, 4, 5, 6, 0];
+        let image = decode(&bmp(32, -1, 0, &[], &data)).unwrap();
+        assert_eq!(image.pixels, vec![3, 2, 1, 255, 6, 5, 4, 255]);
+
+        // RGBA 顺序的位域
+        let masks = [0xff, 0xff00, 0xff_0000, 0xff00_0000];
+        let data = [1, 2, 3, 128, 4, 5, 6, 0];
+        let image = decode(&bmp(32, -1, 6, &masks, &data)).unwrap();
+        assert_eq!(image.pixels, vec![1, 2, 3, 128, 4, 5, 6, 0]);
+    }
+}
@@ -0,0 +1,376 @@
+//! GIF89a 编码, 支持多帧动画和循环播放
+use super::bits::BitWriter;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+const MAX_COLORS: usize = 256;
+const MAX_CODE: u16 = 4096;
+
+pub struct GifEncoder {
+    width: u16,
+    height: u16,
+    data: Vec<u8>,
+}
+
+impl GifEncoder {
+    /// repeat 为循环次数, 0 表示无限循环, None 表示只播放一次
+    pub fn new(width: u32, height: u32, repeat: Option<u16>) -> Result<Self> {
+        if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+            bail!("gif 尺寸超出范围: {}x{}", width, height);
+        }
+        let (width, height) = (width as u16, height as u16);
+        let mut data = b"GIF89a".to_vec();
+        // 逻辑屏幕描述, 不使用全局调色板, 每帧都有自己的调色板
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0]);
+        if let Some(repeat) = repeat {
+            data.extend_from_slice(&[0x21, 0xff, 0x0b]);
+            data.extend_from_slice(b"NETSCAPE2.0");
+            data.extend_from_slice(&[0x03, 0x01]);
+            data.extend_from_slice(&repeat.to_le_bytes());
+            data.push(0);
+        }
+        Ok(GifEncoder {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// 添加一帧, rgba 从上到下逐行存放, delay 单位为 1/100 秒
+    pub fn add_frame(&mut self, rgba: &[u8], delay: u16) -> Result<()> {
+        if rgba.len() != self.width as usize * self.height as usize * 4 {
+            bail!("像素数据长度与图片尺寸不符");
+        }
+        let (palette, indices) = quantize(rgba);
+
+        // 图形控制扩展: 保留上一帧, 无透明色
+        self.data.extend_from_slice(&[0x21, 0xf9, 0x04, 0x04]);
+        self.data.extend_from_slice(&delay.to_le_bytes());
+        self.data.extend_from_slice(&[0, 0]);
+
+        // 图像描述, 带 256 色局部调色板
+        self.data.push(0x2c);
+        self.data.extend_from_slice(&[0, 0, 0, 0]);
+        self.data.extend_from_slice(&self.width.to_le_bytes());
+        self.data.extend_from_slice(&self.height.to_le_bytes());
+        self.data.push(0x80 | 7);
+        for i in 0..MAX_COLORS {
+            let color = palette.get(i).copied().unwrap_or([0, 0, 0]);
+            self.data.extend_from_slice(&color);
+        }
+
+        self.data.push(8);
+        for block in lzw_encode(&indices, 8).chunks(255) {
+            self.data.push(block.len() as u8);
+            self.data.extend_from_slice(block);
+        }
+        self.data.push(0);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data.push(0x3b);
+        self.data
+    }
+
+    pub fn save(self, path: &str) -> Result<()> {
+        let content = self.finish();
+        let mut file = File::create(path)?;
+        file.write_all(&content)?;
+        Ok(())
+    }
+}
+
+/// 把 RGBA 像素量化成不超过 256 色的调色板和每个像素的索引, 忽略 alpha.
+/// 颜色数不超过 256 时直接使用原色, 否则在 15 位颜色直方图上做中位切分
+pub fn quantize(rgba: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut exact: HashMap<[u8; 3], u8> = HashMap::new();
+    for p in rgba.chunks(4) {
+        let color = [p[0], p[1], p[2]];
+        if !exact.contains_key(&color) {
+            if exact.len() == MAX_COLORS {
+                return median_cut(rgba);
+            }
+            exact.insert(color, exact.len() as u8);
+        }
+    }
+    let mut palette = vec![[0; 3]; exact.len()];
+    for (color, &i) in &exact {
+        palette[i as usize] = *color;
+    }
+    let indices = rgba.chunks(4).map(|p| exact[&[p[0], p[1], p[2]]]).collect();
+    (palette, indices)
+}
+
+#[derive(Copy, Clone)]
+struct Bucket {
+    key: usize,
+    count: u32,
+    /// 一个桶可以有上千万个像素, u32 会溢出
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    fn channel(&self, c: usize) -> usize {
+        (self.key >> (10 - c * 5)) & 0x1f
+    }
+}
+
+fn key(p: &[u8]) -> usize {
+    ((p[0] as usize >> 3) << 10) | ((p[1] as usize >> 3) << 5) | (p[2] as usize >> 3)
+}
+
+fn median_cut(rgba: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut histogram = vec![
+        Bucket {
+            key: 0,
+            count: 0,
+            sum: [0; 3],
+        };
+        1 << 15
+    ];
+    for p in rgba.chunks(4) {
+        let bucket = &mut histogram[key(p)];
+        bucket.count += 1;
+        for (sum, &v) in bucket.sum.iter_mut().zip(p) {
+            *sum += v as u64;
+        }
+    }
+    let mut buckets: Vec<Bucket> = histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.count > 0)
+        .map(|(key, b)| Bucket { key, ..*b })
+        .collect();
+
+    // 每个盒子是 buckets 中的一段 [start, end)
+    let mut boxes = vec![(0, buckets.len())];
+    while boxes.len() < MAX_COLORS {
+        // 选颜色范围最大的盒子, 沿范围最大的通道切开
+        let mut best = None;
+        for (i, &(start, end)) in boxes.iter().enumerate() {
+            if end - start < 2 {
+                continue;
+            }
+            for c in 0..3 {
+                let values = buckets[start..end].iter().map(|b| b.channel(c));
+                let range = values.clone().max().unwrap() - values.min().unwrap();
+                if best.is_none_or(|(_, _, r)| range > r) {
+                    best = Some((i, c, range));
+                }
+            }
+        }
+        let (i, c, _) = match best {
+            Some(best) => best,
+            None => break,
+        };
+        let (start, end) = boxes[i];
+        let slice = &mut buckets[start..end];
+        slice.sort_by_key(|b| b.channel(c));
+        let total: u32 = slice.iter().map(|b| b.count).sum();
+        let mut acc = 0;
+        let mut middle = 1;
+        for (j, b) in slice.iter().enumerate() {
+            acc += b.count;
+            if acc * 2 >= total {
+                middle = (j + 1).clamp(1, slice.len() - 1);
+                break;
+            }
+        }
+        boxes[i] = (start, start + middle);
+        boxes.push((start + middle, end));
+    }
+
+    let palette: Vec<[u8; 3]> = boxes
+        .iter()
+        .map(|&(start, end)| {
+            let mut sum = [0u64; 3];
+            let mut count = 0u64;
+            for b in &buckets[start..end] {
+                count += b.count as u64;
+                for (sum, &v) in sum.iter_mut().zip(&b.sum) {
+                    *sum += v;
+                }
+            }
+            [
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+            ]
+        })
+        .collect();
+
+    let mut lookup = vec![0u8; 1 << 15];
+    for b in &buckets {
+        let count = b.count as u64;
+        let color = [
+            (b.sum[0] / count) as i32,
+            (b.sum[1] / count) as i32,
+            (b.sum[2] / count) as i32,
+        ];
+        lookup[b.key] = nearest(&palette, color);
+    }
+    let indices = rgba.chunks(4).map(|p| lookup[key(p)]).collect();
+    (palette, indices)
+}
+
+fn nearest(palette: &[[u8; 3]], color: [i32; 3]) -> u8 {
+    let distance = |p: &[u8; 3]| -> i32 {
+        (0..3)
+            .map(|c| {
+                let d = p[c] as i32 - color[c];
+                d * d
+            })
+            .sum()
+    };
+    let (i, _) = palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| distance(p))
+        .unwrap();
+    i as u8
+}
+
+/// 变长 LZW 压缩, 码表满 4096 项时输出清除码重新开始
+pub fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter::new();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size as u32 + 1;
+    writer.write_bits(clear as u32, size);
+
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(&first) => first as u16,
+        None => {
+            writer.write_bits(end as u32, size);
+            return writer.finish();
+        }
+    };
+    for &c in iter {
+        if let Some(&code) = table.get(&(prefix, c)) {
+            prefix = code;
+            continue;
+        }
+        writer.write_bits(prefix as u32, size);
+        if next < MAX_CODE {
+            table.insert((prefix, c), next);
+            next += 1;
+            // 解码端比编码端晚一项建表, 所以要等码表超出当前位宽才加宽
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            writer.write_bits(clear as u32, size);
+            table.clear();
+            next = end + 1;
+            size = min_code_size as u32 + 1;
+        }
+        prefix = c as u16;
+    }
+    writer.write_bits(prefix as u32, size);
+    writer.write_bits(end as u32, size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::bits::BitReader;
+
+    /// 按 GIF 规范解码 LZW, 码表超出当前位宽时加宽, 遇到清除码重新开始
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let mut reader = BitReader::new(data);
+        let mut table: Vec<Vec<u8>> = vec![];
+        let mut size = min_code_size as u32 + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = vec![];
+        loop {
+            let code = reader.read_bits(size).expect("缺少结束码") as usize;
+            if code == clear {
+                table = (0..clear).map(|i| vec![i as u8]).collect();
+                table.extend([vec![], vec![]]);
+                size = min_code_size as u32 + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                // 编码端刚加入的项, 等于前一项加上它自己的第一个字节
+                (None, Some(previous)) if code == table.len() => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                _ => panic!("无效的编码 {}", code),
+            };
+            if let Some(mut previous) = previous.take() {
+                if table.len() < MAX_CODE as usize {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        // xorshift 伪随机序列, 码表很快填满, 会多次输出清除码
+        let mut state = 0x2545_f491u32;
+        let random: Vec<u8> = (0..50_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        // 单色的长序列, 每个编码比上一个长一个字节, 位宽逐步增长
+        let flat = vec![7u8; 200_000];
+        let small: Vec<u8> = random.iter().map(|v| v & 3).collect();
+        for (indices, min_code_size) in [
+            (&random, 8),
+            (&flat, 8),
+            (&small, 2),
+            (&vec![], 8),
+            (&vec![1], 2),
+        ] {
+            let decoded = lzw_decode(&lzw_encode(indices, min_code_size), min_code_size);
+            assert!(decoded == *indices, "{} {}", indices.len(), min_code_size);
+        }
+    }
+
+    #[test]
+    fn test_quantize_exact() {
+        let rgba = [255, 0, 0, 255, 0, 255, 0, 255, 255, 0, 0, 255];
+        let (palette, indices) = quantize(&rgba);
+        assert_eq!(palette, vec![[255, 0, 0], [0, 255, 0]]);
+        assert_eq!(indices, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn test_quantize_limit() {
+        let rgba: Vec<u8> = (0..64 * 64)
+            .flat_map(|i: u32| vec![(i * 7) as u8, (i / 3) as u8, (i >> 4) as u8, 255])
+            .collect();
+        let (palette, indices) = quantize(&rgba);
+        assert!(palette.len() <= MAX_COLORS);
+        assert_eq!(indices.len(), 64 * 64);
+    }
+}
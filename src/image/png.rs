@@ -0,0 +1,505 @@
+//! PNG 编解码. 编码输出 8 位 RGBA 图片; 解码支持所有颜色类型, 位深, tRNS 透明色和 Adam7 隔行,
+//! 统一转换成 8 位 RGBA
+use super::{checked_size, zlib, RgbaImage};
+use anyhow::{anyhow, bail, Result};
+use std::fs::File;
+use std::io::prelude::*;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut c = 0xffffffff;
+    for &b in bytes {
+        c = CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8);
+    }
+    c ^ 0xffffffff
+}
+
+/// 把 RGBA 像素 (从上到下逐行存放) 编码成 PNG 文件内容
+pub fn encode(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>> {
+    if width == 0 || height == 0 {
+        bail!("png 尺寸不能为 0: {}x{}", width, height);
+    }
+    let stride = width as usize * 4;
+    if rgba.len() != stride * height as usize {
+        bail!("像素数据长度与图片尺寸不符");
+    }
+
+    let mut ihdr = vec![];
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 位深 8, 颜色类型 6 (RGBA), 默认压缩, 默认滤波, 不隔行
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &ihdr);
+    write_chunk(&mut out, b"IDAT", &zlib::compress(&filter(rgba, stride)));
+    write_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+pub fn save(path: &str, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let content = encode(width, height, rgba)?;
+    let mut file = File::create(path)?;
+    file.write_all(&content)?;
+    Ok(())
+}
+
+pub fn load(path: &str) -> Result<RgbaImage> {
+    let mut file = File::open(path)?;
+    let mut content = vec![];
+    file.read_to_end(&mut content)?;
+    decode(&content)
+}
+
+pub fn decode(content: &[u8]) -> Result<RgbaImage> {
+    if content.len() < 8 || content[..8] != SIGNATURE {
+        bail!("不是 png 文件");
+    }
+    let mut header = None;
+    let mut palette = vec![];
+    let mut transparency = vec![];
+    let mut data = vec![];
+    let mut rest = &content[8..];
+    loop {
+        if rest.len() < 12 {
+            bail!("png 数据不完整");
+        }
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let chunk = rest
+            .get(4..8 + length)
+            .ok_or_else(|| anyhow!("png 数据不完整"))?;
+        let crc = rest
+            .get(8 + length..12 + length)
+            .ok_or_else(|| anyhow!("png 数据不完整"))?;
+        if crc32(chunk) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            bail!("png 数据块校验错误");
+        }
+        let (kind, body) = chunk.split_at(4);
+        match kind {
+            b"IHDR" => header = Some(Header::parse(body)?),
+            b"PLTE" => palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            b"tRNS" => transparency = body.to_vec(),
+            b"IDAT" => data.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[12 + length..];
+    }
+    let header = header.ok_or_else(|| anyhow!("png 缺少头部"))?;
+    if header.color_type == 3 && palette.is_empty() {
+        bail!("png 缺少调色板");
+    }
+    let (width, height) = (header.width as usize, header.height as usize);
+    // 不隔行时只有一遍, 覆盖整张图
+    let passes: &[(usize, usize, usize, usize)] = if header.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+    // 解压时不能超过文件头中的尺寸, 解压之后再和实际长度核对
+    let expected = filtered_size(&header, passes).ok_or_else(|| anyhow!("png 尺寸过大"))?;
+    let filtered = zlib::decompress(&data, expected)?;
+    if expected != filtered.len() {
+        bail!("png 像素数据长度错误");
+    }
+    let mut pixels = vec![0; checked_size("png", &[width, height, 4])?];
+    let mut offset = 0;
+    for &(x0, y0, dx, dy) in passes {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let stride = (pass_width * header.bits_per_pixel()).div_ceil(8);
+        let size = (stride + 1) * pass_height;
+        let pass = filtered
+            .get(offset..offset + size)
+            .ok_or_else(|| anyhow!("png 像素数据长度错误"))?;
+        offset += size;
+        let bpp = header.bits_per_pixel().div_ceil(8);
+        let rows = unfilter(pass, stride, bpp)?;
+        for (j, row) in rows.chunks(stride).enumerate() {
+            for i in 0..pass_width {
+                let rgba = header.pixel(row, i, &palette, &transparency)?;
+                let index = ((y0 + j * dy) * width + x0 + i * dx) * 4;
+                pixels[index..index + 4].copy_from_slice(&rgba);
+            }
+        }
+    }
+    Ok(RgbaImage {
+        width: header.width,
+        height: header.height,
+        pixels,
+    })
+}
+
+/// 所有遍滤波后的数据长度, 每行前面有一个字节的滤波类型. 溢出时返回 None
+fn filtered_size(header: &Header, passes: &[(usize, usize, usize, usize)]) -> Option<usize> {
+    let (width, height) = (header.width as usize, header.height as usize);
+    passes.iter().try_fold(0usize, |total, &(x0, y0, dx, dy)| {
+        let pass_width = (width + dx - 1 - x0) / dx;
+        let pass_height = (height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            return Some(total);
+        }
+        let stride = pass_width.checked_mul(header.bits_per_pixel())?.div_ceil(8);
+        total.checked_add((stride + 1).checked_mul(pass_height)?)
+    })
+}
+
+/// Adam7 隔行的 7 遍: 起始列, 起始行, 列间隔, 行间隔
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct Header {
+    width: u32,
+    height: u32,
+    depth: usize,
+    /// 0 灰度, 2 RGB, 3 调色板, 4 灰度和透明度, 6 RGBA
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(body: &[u8]) -> Result<Header> {
+        if body.len() != 13 {
+            bail!("png 头部长度错误");
+        }
+        let header = Header {
+            width: u32::from_be_bytes([body[0], body[1], body[2], body[3]]),
+            height: u32::from_be_bytes([body[4], body[5], body[6], body[7]]),
+            depth: body[8] as usize,
+            color_type: body[9],
+            interlaced: body[12] == 1,
+        };
+        let depths: &[usize] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => bail!("png 颜色类型错误 {}", header.color_type),
+        };
+        if !depths.contains(&header.depth) {
+            bail!(
+                "png 颜色类型 {} 不支持位深 {}",
+                header.color_type,
+                header.depth
+            );
+        }
+        if body[10] != 0 || body[11] != 0 || body[12] > 1 {
+            bail!("png 压缩, 滤波或隔行方式错误");
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.depth
+    }
+
+    /// 一行中第 i 个像素转换成 8 位 RGBA
+    fn pixel(
+        &self,
+        row: &[u8],
+        i: usize,
+        palette: &[[u8; 3]],
+        transparency: &[u8],
+    ) -> Result<[u8; 4]> {
+        let channels = self.channels();
+        // 原始的样本值, 小于 8 位的样本从高位开始排列
+        let sample = |c: usize| -> u16 {
+            let n = i * channels + c;
+            match self.depth {
+                16 => u16::from_be_bytes([row[n * 2], row[n * 2 + 1]]),
+                8 => row[n] as u16,
+                depth => {
+                    let bit = n * depth;
+                    let shift = 8 - depth - bit % 8;
+                    (row[bit / 8] >> shift) as u16 & ((1 << depth) - 1)
+                }
+            }
+        };
+        let scale = |v: u16| -> u8 {
+            match self.depth {
+                16 => (v >> 8) as u8,
+                depth => (v as u32 * 255 / ((1 << depth) - 1)) as u8,
+            }
+        };
+        // tRNS 对灰度和 RGB 图片给出一个完全透明的颜色
+        let key = |c: usize| {
+            transparency
+                .get(c * 2..c * 2 + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        Ok(match self.color_type {
+            0 => {
+                let v = sample(0);
+                let alpha = if key(0) == Some(v) { 0 } else { 255 };
+                let g = scale(v);
+                [g, g, g, alpha]
+            }
+            2 => {
+                let rgb = [sample(0), sample(1), sample(2)];
+                let transparent = (0..3).all(|c| key(c) == Some(rgb[c]));
+                let alpha = if transparent { 0 } else { 255 };
+                [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), alpha]
+            }
+            3 => {
+                let index = sample(0) as usize;
+                let [r, g, b] = *palette
+                    .get(index)
+                    .ok_or_else(|| anyhow!("png 调色板下标 {} 超出范围", index))?;
+                [r, g, b, transparency.get(index).copied().unwrap_or(255)]
+            }
+            4 => {
+                let g = scale(sample(0));
+                [g, g, g, scale(sample(1))]
+            }
+            _ => [
+                scale(sample(0)),
+                scale(sample(1)),
+                scale(sample(2)),
+                scale(sample(3)),
+            ],
+        })
+    }
+}
+
+fn unfilter(data: &[u8], stride: usize, bpp: usize) -> Result<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    for (y, line) in data.chunks(stride + 1).enumerate() {
+        let kind = line[0];
+        let start = y * stride;
+        for i in 0..stride {
+            let a = if i >= bpp { out[start + i - bpp] } else { 0 };
+            let b = if y > 0 { out[start + i - stride] } else { 0 };
+            let c = if y > 0 && i >= bpp {
+                out[start + i - stride - bpp]
+            } else {
+                0
+            };
+            let x = line[i + 1];
+            let value = match kind {
+                0 => x,
+                1 => x.wrapping_add(a),
+                2 => x.wrapping_add(b),
+                3 => x.wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => x.wrapping_add(paeth(a, b, c)),
+                _ => bail!("png 滤波类型错误"),
+            };
+            out.push(value);
+        }
+    }
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// 每行选择绝对值和最小的滤波方式, 数据更容易压缩
+fn filter(rgba: &[u8], stride: usize) -> Vec<u8> {
+    let bpp = 4;
+    let mut out = Vec::with_capacity(rgba.len() + rgba.len() / stride.max(1));
+    let zero = vec![0; stride];
+    let mut candidates = vec![vec![0u8; stride]; 5];
+    for (y, row) in rgba.chunks(stride).enumerate() {
+        let prior = if y == 0 {
+            &zero[..]
+        } else {
+            &rgba[(y - 1) * stride..y * stride]
+        };
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prior[i];
+            let c = if i >= bpp { prior[i - bpp] } else { 0 };
+            let x = row[i];
+            candidates[0][i] = x;
+            candidates[1][i] = x.wrapping_sub(a);
+            candidates[2][i] = x.wrapping_sub(b);
+            candidates[3][i] = x.wrapping_sub(((a as u16 + b as u16) / 2) as u8);
+            candidates[4][i] = x.wrapping_sub(paeth(a, b, c));
+        }
+        let (kind, best) = candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, line)| {
+                line.iter()
+                    .map(|&v| (v as i8).unsigned_abs() as u32)
+                    .sum::<u32>()
+            })
+            .unwrap();
+        out.push(kind as u8);
+        out.extend_from_slice(best);
+    }
+    out
+}
+
+pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let pixels: Vec<u8> = (0..7 * 5 * 4).map(|i| (i * 37 % 256) as u8).collect();
+        let image = decode(&encode(7, 5, &pixels).unwrap()).unwrap();
+        assert_eq!((image.width, image.height), (7, 5));
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn test_encode_empty() {
+        assert!(encode(0, 10, &[]).is_err());
+        assert!(encode(10, 0, &[]).is_err());
+    }
+
+    #[test]
+    fn test_encode_header() {
+        let png = encode(2, 1, &[255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+
+    /// 用给定的头部和已经滤波的数据组成 png, extra 是额外的数据块
+    fn raw_png(
+        width: u32,
+        height: u32,
+        info: [u8; 5],
+        extra: &[(&[u8; 4], &[u8])],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&info);
+        let mut out = SIGNATURE.to_vec();
+        write_chunk(&mut out, b"IHDR", &ihdr);
+        for (kind, body) in extra {
+            write_chunk(&mut out, kind, body);
+        }
+        write_chunk(&mut out, b"IDAT", &zlib::compress(data));
+        write_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test]
+    fn test_color_types() {
+        // 1 位灰度: 每行 3 个像素占 1 个字节
+        let image = decode(&raw_png(3, 1, [1, 0, 0, 0, 0], &[], &[0, 0b1010_0000])).unwrap();
+        assert_eq!(
+            image.pixels,
+            [255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255]
+        );
+
+        // 4 位调色板, 第 2 个颜色半透明
+        let palette: &[u8] = &[255, 0, 0, 0, 0, 255];
+        let chunks: &[(&[u8; 4], &[u8])] = &[(b"PLTE", palette), (b"tRNS", &[255, 128])];
+        let image = decode(&raw_png(2, 1, [4, 3, 0, 0, 0], chunks, &[0, 0x10])).unwrap();
+        assert_eq!(image.pixels, [0, 0, 255, 128, 255, 0, 0, 255]);
+        let error = decode(&raw_png(2, 1, [4, 3, 0, 0, 0], chunks, &[0, 0x12]));
+        assert!(error.is_err());
+
+        // 16 位 RGB, tRNS 指定的颜色完全透明
+        let data = [0, 0x12, 0x34, 0xff, 0xff, 0, 1, 0, 0, 0, 0, 0, 0];
+        let chunks: &[(&[u8; 4], &[u8])] = &[(b"tRNS", &[0, 0, 0, 0, 0, 0])];
+        let image = decode(&raw_png(2, 1, [16, 2, 0, 0, 0], chunks, &data)).unwrap();
+        assert_eq!(image.pixels, [0x12, 0xff, 0, 255, 0, 0, 0, 0]);
+
+        // 8 位灰度和透明度, 第二行用 up 滤波
+        let image = decode(&raw_png(1, 2, [8, 4, 0, 0, 0], &[], &[0, 10, 20, 2, 5, 5])).unwrap();
+        assert_eq!(image.pixels, [10, 10, 10, 20, 15, 15, 15, 25]);
+    }
+
+    #[test]
+    fn test_oversized_header() {
+        // 文件头声明的尺寸远超实际数据, 不能先按尺寸分配像素
+        for interlace in [0, 1] {
+            let png = raw_png(60000, 60000, [8, 6, 0, 0, interlace], &[], &[0; 5]);
+            assert!(decode(&png).is_err());
+        }
+        // 解压出的数据远多于文件头声明的尺寸
+        assert!(decode(&raw_png(1, 1, [8, 6, 0, 0, 0], &[], &vec![0; 1 << 20])).is_err());
+    }
+
+    #[test]
+    fn test_adam7() {
+        let (width, height) = (5, 3);
+        let pixels: Vec<u8> = (0..width * height * 4).map(|i| (i * 7) as u8).collect();
+        let mut data = vec![];
+        for (x0, y0, dx, dy) in ADAM7 {
+            for y in (y0..height).step_by(dy) {
+                if x0 >= width {
+                    continue;
+                }
+                data.push(0);
+                for x in (x0..width).step_by(dx) {
+                    let i = (y * width + x) * 4;
+                    data.extend_from_slice(&pixels[i..i + 4]);
+                }
+            }
+        }
+        let png = raw_png(width as u32, height as u32, [8, 6, 0, 0, 1], &[], &data);
+        assert_eq!(decode(&png).unwrap().pixels, pixels);
+    }
+}
@@ -0,0 +1,99 @@
+//! 二进制 PPM (P6) 和 PGM (P5) 解码
+use super::{checked_size, RgbaImage};
+use anyhow::{anyhow, bail, Result};
+
+pub fn decode(content: &[u8]) -> Result<RgbaImage> {
+    let channels = match content.get(..2) {
+        Some(b"P6") => 3,
+        Some(b"P5") => 1,
+        _ => bail!("不是二进制 ppm 或 pgm 文件"),
+    };
+    let mut offset = 2;
+    let mut fields = [0; 3];
+    for field in &mut fields {
+        *field = header_number(content, &mut offset)?;
+    }
+    let [width, height, max] = fields;
+    if max == 0 || max > 65535 {
+        bail!("ppm 最大值错误 {}", max);
+    }
+    // 文件头和像素数据之间只有一个空白字符
+    offset += 1;
+    let bytes = if max < 256 { 1 } else { 2 };
+    let size = checked_size("ppm", &[width, height, channels * bytes])?;
+    let data = content
+        .get(offset..)
+        .and_then(|data| data.get(..size))
+        .ok_or_else(|| anyhow!("ppm 数据不完整"))?;
+    let samples: Vec<u8> = data
+        .chunks_exact(bytes)
+        .map(|s| {
+            let v = match *s {
+                [v] => v as usize,
+                [high, low] => u16::from_be_bytes([high, low]) as usize,
+                _ => unreachable!(),
+            };
+            (v.min(max) * 255 / max) as u8
+        })
+        .collect();
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for p in samples.chunks_exact(channels) {
+        match *p {
+            [g] => pixels.extend_from_slice(&[g, g, g, 255]),
+            [r, g, b] => pixels.extend_from_slice(&[r, g, b, 255]),
+            _ => unreachable!(),
+        }
+    }
+    Ok(RgbaImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// 跳过空白和 # 开头的注释, 读取一个十进制数
+fn header_number(content: &[u8], offset: &mut usize) -> Result<usize> {
+    loop {
+        match content.get(*offset) {
+            Some(b'#') => {
+                while !matches!(content.get(*offset), Some(b'\n') | None) {
+                    *offset += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => *offset += 1,
+            Some(_) => break,
+            None => bail!("ppm 文件头不完整"),
+        }
+    }
+    let start = *offset;
+    while content.get(*offset).is_some_and(u8::is_ascii_digit) {
+        *offset += 1;
+    }
+    std::str::from_utf8(&content[start..*offset])
+        .unwrap()
+        .parse()
+        .map_err(|_| anyhow!("ppm 文件头格式错误"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut data = "P6\n# 注释\n2 1\n255\n".as_bytes().to_vec();
+        data.extend_from_slice(&[255, 0, 0, 1, 2, 3]);
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, vec![255, 0, 0, 255, 1, 2, 3, 255]);
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        let mut data = b"P5 1 2 1023 ".to_vec();
+        data.extend_from_slice(&[0x03, 0xff, 0x00, 0x00]);
+        let image = decode(&data).unwrap();
+        assert_eq!(image.pixels, vec![255, 255, 255, 255, 0, 0, 0, 255]);
+
+        assert!(decode(b"P6 99999999999 99999999999 255 ").is_err());
+        assert!(decode(b"P6 60000 60000 255 ").is_err());
+    }
+}
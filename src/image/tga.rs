@@ -0,0 +1,199 @@
+//! TGA 解码, 支持调色板, 真彩色和灰度图片以及它们的 RLE 压缩格式
+use super::{checked_size, RgbaImage};
+use anyhow::{anyhow, bail, Result};
+
+const HEADER_SIZE: usize = 18;
+
+pub fn decode(content: &[u8]) -> Result<RgbaImage> {
+    let eof = || anyhow!("tga 数据不完整");
+    let header = content.get(..HEADER_SIZE).ok_or_else(eof)?;
+    let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]) as usize;
+    let id_length = header[0] as usize;
+    let has_map = header[1] == 1;
+    let image_type = header[2];
+    let (map_first, map_length, map_depth) = (u16_at(3), u16_at(5), header[7] as usize);
+    let (width, height) = (u16_at(12), u16_at(14));
+    let depth = header[16] as usize;
+    let descriptor = header[17];
+
+    let rle = image_type >= 9;
+    let kind = image_type & 7;
+    match (kind, depth) {
+        (1, 8) if has_map => {}
+        (2, 15 | 16 | 24 | 32) | (3, 8 | 16) => {}
+        _ => bail!("不支持的 tga 类型 {}, {} 位", image_type, depth),
+    }
+
+    let mut offset = HEADER_SIZE + id_length;
+    let mut palette = vec![];
+    if has_map {
+        if !matches!(map_depth, 15 | 16 | 24 | 32) {
+            bail!("不支持的 tga 调色板颜色位数 {}", map_depth);
+        }
+        let entry = map_depth.div_ceil(8);
+        let data = content
+            .get(offset..offset + map_length * entry)
+            .ok_or_else(eof)?;
+        palette = data
+            .chunks_exact(entry)
+            .map(|p| color(p, map_depth, 2))
+            .collect::<Result<Vec<_>>>()?;
+        offset += map_length * entry;
+    }
+
+    let bytes = depth.div_ceil(8);
+    let count = width * height;
+    let size = checked_size("tga", &[count, bytes])?;
+    let data = &content[offset.min(content.len())..];
+    let raw = if rle {
+        unpack_rle(data, bytes, count)?
+    } else {
+        data.get(..size).ok_or_else(eof)?.to_vec()
+    };
+    let mut colors = raw
+        .chunks_exact(bytes)
+        .map(|p| match kind {
+            1 => {
+                let index = (p[0] as usize)
+                    .checked_sub(map_first)
+                    .filter(|&i| i < palette.len())
+                    .ok_or_else(|| anyhow!("tga 调色板下标 {} 超出范围", p[0]))?;
+                Ok(palette[index])
+            }
+            _ => color(p, depth, kind),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // 带透明度的格式常被当作没有透明度写入, 透明度全为 0 时当作不透明
+    if colors.iter().all(|c| c[3] == 0) {
+        for c in &mut colors {
+            c[3] = 255;
+        }
+    }
+
+    // 默认从左下角开始存放, 第 4 位表示从右到左, 第 5 位表示从上到下
+    let right_to_left = descriptor & 0x10 != 0;
+    let top_down = descriptor & 0x20 != 0;
+    let mut pixels = Vec::with_capacity(count * 4);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        for x in 0..width {
+            let column = if right_to_left { width - 1 - x } else { x };
+            pixels.extend_from_slice(&colors[row * width + column]);
+        }
+    }
+    Ok(RgbaImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// 每个包的第一个字节最高位为 1 时是重复像素, 否则是原样存放的像素, 低 7 位加 1 是像素个数.
+/// 包可以跨行
+fn unpack_rle(data: &[u8], bytes: usize, count: usize) -> Result<Vec<u8>> {
+    let eof = || anyhow!("tga 数据不完整");
+    // 每个包至少 1 + bytes 个字节, 最多展开成 128 个像素
+    if count > data.len() / (1 + bytes) * 128 {
+        return Err(eof());
+    }
+    let mut out = Vec::with_capacity(count * bytes);
+    let mut offset = 0;
+    while out.len() < count * bytes {
+        let packet = *data.get(offset).ok_or_else(eof)?;
+        let n = (packet & 0x7f) as usize + 1;
+        offset += 1;
+        if packet & 0x80 != 0 {
+            let pixel = data.get(offset..offset + bytes).ok_or_else(eof)?;
+            for _ in 0..n {
+                out.extend_from_slice(pixel);
+            }
+            offset += bytes;
+        } else {
+            out.extend_from_slice(data.get(offset..offset + n * bytes).ok_or_else(eof)?);
+            offset += n * bytes;
+        }
+    }
+    out.truncate(count * bytes);
+    Ok(out)
+}
+
+/// 真彩色按 BGR(A) 存放, 15 和 16 位是 ARRRRRGG GGGBBBBB; 灰度是亮度和可选的透明度
+fn color(p: &[u8], depth: usize, kind: u8) -> Result<[u8; 4]> {
+    Ok(match (kind, depth) {
+        (3, 8) => [p[0], p[0], p[0], 255],
+        (3, 16) => [p[0], p[0], p[0], p[1]],
+        (_, 15 | 16) => {
+            let v = u16::from_le_bytes([p[0], p[1]]);
+            let expand = |c: u16| ((c & 0x1f) * 255 / 31) as u8;
+            let alpha = if depth == 16 && v & 0x8000 == 0 {
+                0
+            } else {
+                255
+            };
+            [expand(v >> 10), expand(v >> 5), expand(v), alpha]
+        }
+        (_, 24) => [p[2], p[1], p[0], 255],
+        (_, 32) => [p[2], p[1], p[0], p[3]],
+        _ => bail!("不支持的 tga 颜色位数 {}", depth),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tga(image_type: u8, depth: u8, descriptor: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&[depth, descriptor]);
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_rle_bottom_up() {
+        // 一个跨行的 3 像素重复包, 然后 1 个原样像素
+        let data = [0x82, 0, 0, 255, 0x00, 1, 2, 3];
+        let image = decode(&tga(10, 24, 0, &data)).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![255, 0, 0, 255, 3, 2, 1, 255, 255, 0, 0, 255, 255, 0, 0, 255]
+        );
+        assert!(decode(&tga(10, 24, 0, &data[..5])).is_err());
+
+        // 文件头中的宽和高远超实际数据
+        for image_type in [2, 10] {
+            let mut huge = tga(image_type, 24, 0, &data);
+            huge[12..16].copy_from_slice(&[0xff; 4]);
+            assert!(decode(&huge).is_err());
+        }
+    }
+
+    #[test]
+    fn test_top_down_alpha_and_palette() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0];
+        let image = decode(&tga(2, 32, 0x28, &data)).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![3, 2, 1, 4, 7, 6, 5, 8, 11, 10, 9, 12, 15, 14, 13, 0]
+        );
+
+        // 2 个 24 位的调色板颜色, 下标从 1 开始
+        let mut data = tga(1, 8, 0x20, &[]);
+        data[1] = 1;
+        data[3..8].copy_from_slice(&[1, 0, 2, 0, 24]);
+        data.extend_from_slice(&[0, 0, 255, 255, 0, 0, 1, 2, 2, 1]);
+        let image = decode(&data).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![255, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 255, 255, 0, 0, 255]
+        );
+
+        // 调色板颜色位数为 0 或不支持的位数
+        for map_depth in [0, 8] {
+            data[7] = map_depth;
+            assert!(decode(&data).is_err());
+        }
+    }
+}
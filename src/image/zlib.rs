@@ -0,0 +1,439 @@
+//! zlib (RFC 1950) 和 deflate (RFC 1951) 压缩与解压
+use super::bits::{BitReader, BitWriter};
+use anyhow::{anyhow, bail, Result};
+
+const WINDOW_SIZE: usize = 1 << 15;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const HASH_BITS: u32 = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// 每个位置最多向前尝试的匹配次数, 用压缩率换速度
+const MAX_CHAIN: usize = 128;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 是保证 b 不溢出的最大分段长度
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// 压缩成 zlib 数据流
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate, 32K 窗口; FLG: 默认压缩级别, 无预设字典
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// 用 LZ77 和固定 huffman 编码压缩成单个 deflate 块
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // BFINAL = 1, BTYPE = 01 (固定 huffman)
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut [usize], prev: &mut [usize], pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(&data[pos..]);
+            prev[pos & WINDOW_MASK] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        if length >= MIN_MATCH {
+            writer.write_length(length);
+            writer.write_distance(distance);
+            for p in pos..pos + length {
+                insert(&mut head, &mut prev, p);
+            }
+            pos += length;
+        } else {
+            writer.write_literal(data[pos] as u16);
+            insert(&mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+    writer.write_literal(256);
+    writer.finish()
+}
+
+/// 解压 zlib 数据流并校验 adler32, 解压结果超过 limit 字节时返回错误
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if data.len() < 6 {
+        bail!("zlib 数据不完整");
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        bail!("zlib 头部错误");
+    }
+    if flg & 0x20 != 0 {
+        bail!("不支持 zlib 预设字典");
+    }
+    let out = inflate(&data[2..], limit)?;
+    let checksum = data.len() - 4;
+    let expected = u32::from_be_bytes([
+        data[checksum],
+        data[checksum + 1],
+        data[checksum + 2],
+        data[checksum + 3],
+    ]);
+    if adler32(&out) != expected {
+        bail!("zlib 校验和错误");
+    }
+    Ok(out)
+}
+
+/// 解压 deflate 数据, 支持不压缩, 固定 huffman 和动态 huffman 三种块.
+/// 很小的数据可以解压出几 GB, 超过 limit 字节时立即返回错误
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let eof = || anyhow!("deflate 数据不完整");
+    let mut reader = BitReader::new(data);
+    let mut out = vec![];
+    loop {
+        let last = reader.read_bits(1).ok_or_else(eof)?;
+        match reader.read_bits(2).ok_or_else(eof)? {
+            0 => {
+                let header = reader.read_bytes(4).ok_or_else(eof)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    bail!("deflate 不压缩块长度错误");
+                }
+                if out.len() + len as usize > limit {
+                    bail!("deflate 解压数据超过 {} 字节", limit);
+                }
+                out.extend_from_slice(reader.read_bytes(len as usize).ok_or_else(eof)?);
+            }
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, limit, &literals, &distances)?;
+            }
+            _ => bail!("deflate 块类型错误"),
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+/// 范式 huffman 码表, 按码长统计个数, 按码值顺序排列符号
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader
+                .read_bits(1)
+                .ok_or_else(|| anyhow!("deflate 数据不完整"))? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        bail!("deflate huffman 编码错误")
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let mut read = |n| {
+        reader
+            .read_bits(n)
+            .ok_or_else(|| anyhow!("deflate 数据不完整"))
+    };
+    let hlit = read(5)? as usize + 257;
+    let hdist = read(5)? as usize + 1;
+    let hclen = read(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for &i in &ORDER[..hclen] {
+        code_lengths[i] = read(3)? as u8;
+    }
+    let code_table = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < hlit + hdist {
+        let symbol = code_table.decode(reader)?;
+        let mut read = |n| {
+            reader
+                .read_bits(n)
+                .ok_or_else(|| anyhow!("deflate 数据不完整"))
+        };
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| anyhow!("deflate 码长重复错误"))?;
+                (previous, 3 + read(2)?)
+            }
+            17 => (0, 3 + read(3)?),
+            _ => (0, 11 + read(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > hlit + hdist {
+        bail!("deflate 码长数量错误");
+    }
+    let (literals, distances) = lengths.split_at(hlit);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<()> {
+    loop {
+        // 每个符号最多输出 258 字节, 超出一点之后再报错也没有问题
+        if out.len() > limit {
+            bail!("deflate 解压数据超过 {} 字节", limit);
+        }
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let mut read = |n: u8| {
+                    reader
+                        .read_bits(n as u32)
+                        .ok_or_else(|| anyhow!("deflate 数据不完整"))
+                };
+                let length = LENGTH_BASE[i] as usize + read(LENGTH_EXTRA[i])? as usize;
+                let d = distances.decode(reader)? as usize;
+                if d >= DIST_BASE.len() {
+                    bail!("deflate 距离编码错误");
+                }
+                let distance = DIST_BASE[d] as usize
+                    + reader
+                        .read_bits(DIST_EXTRA[d] as u32)
+                        .ok_or_else(|| anyhow!("deflate 数据不完整"))?
+                        as usize;
+                if distance > out.len() {
+                    bail!("deflate 距离超出已解压数据");
+                }
+                // 距离可能小于长度, 只能逐字节复制
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => bail!("deflate 长度编码错误"),
+        }
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&data[pos..])];
+    let mut chain = 0;
+    while candidate != usize::MAX && chain < MAX_CHAIN {
+        let distance = pos - candidate;
+        if distance > WINDOW_SIZE - 1 {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[pos..pos + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, distance);
+            if length == max_length {
+                break;
+            }
+        }
+        let next = prev[candidate & WINDOW_MASK];
+        // 链表中的位置只会越来越靠前, 否则说明该槽位已经被覆盖
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+        chain += 1;
+    }
+    best
+}
+
+impl BitWriter {
+    /// huffman 码从高位开始写
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits(reversed, length);
+    }
+
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let i = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap();
+        self.write_literal(257 + i as u16);
+        let extra = length as u32 - LENGTH_BASE[i] as u32;
+        self.write_bits(extra, LENGTH_EXTRA[i] as u32);
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let i = DIST_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        self.write_code(i as u32, 5);
+        let extra = distance as u32 - DIST_BASE[i] as u32;
+        self.write_bits(extra, DIST_EXTRA[i] as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..20000u32)
+            .map(|i| ((i % 251) ^ (i / 97)) as u8)
+            .collect();
+        assert_eq!(decompress(&compress(&data), data.len()).unwrap(), data);
+        assert_eq!(decompress(&compress(&[]), 0).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_limit() {
+        // 1 MB 的 0 压缩后只有几 KB
+        let data = vec![0; 1 << 20];
+        let compressed = compress(&data);
+        assert!(compressed.len() < 16 * 1024, "{}", compressed.len());
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+        assert!(decompress(&compressed, data.len()).is_ok());
+        // 不压缩块
+        let stored = [
+            120, 1, 1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100, 9, 60, 2, 146,
+        ];
+        assert!(decompress(&stored, 5).is_err());
+    }
+
+    #[test]
+    fn test_inflate_dynamic() {
+        // 由 python zlib.compress(data, 9) 生成, 使用动态 huffman 块
+        let data = [
+            120, 218, 37, 140, 193, 17, 0, 48, 8, 194, 86, 113, 181, 0, 251, 207, 80, 180, 250,
+            240, 128, 8, 136, 201, 24, 6, 155, 136, 26, 29, 119, 201, 172, 168, 231, 117, 101, 164,
+            146, 7, 74, 164, 148, 166, 178, 193, 141, 165, 207, 150, 233, 29, 185, 79, 185, 232,
+            58, 179, 37, 236, 43, 60, 23, 185, 43, 66,
+        ];
+        let expected = b"aaba d caa accadbaabaaaacacaad abaadbacaababcabb ca accabbadaacb \
+            a abcaaaaaacbbdbacaaca bac bcbabdaaaaabaaaadababaabbaaa";
+        assert_eq!(decompress(&data, 1024).unwrap(), expected.to_vec());
+    }
+
+    #[test]
+    fn test_inflate_stored() {
+        let data = [
+            120, 1, 1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100, 9, 60, 2, 146,
+        ];
+        assert_eq!(decompress(&data, 6).unwrap(), b"stored".to_vec());
+    }
+}
@@ -5,6 +5,55 @@ pub trait Interpolate {
     fn interpolate(&self, other: &Self, factor: f32) -> Self;
 }
 
+/// 按重心坐标混合三个值, 透视校正由光栅化在计算 weights 时完成
+pub trait Barycentric {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self;
+}
+
+impl Barycentric for f32 {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        a * weights[0] + b * weights[1] + c * weights[2]
+    }
+}
+
+impl Barycentric for Vector {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        let x = f32::barycentric(&a.x, &b.x, &c.x, weights);
+        let y = f32::barycentric(&a.y, &b.y, &c.y, weights);
+        let z = f32::barycentric(&a.z, &b.z, &c.z, weights);
+        let w = f32::barycentric(&a.w, &b.w, &c.w, weights);
+        Self { x, y, z, w }
+    }
+}
+
+impl Barycentric for Color {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        let mix = |a: u8, b: u8, c: u8| {
+            f32::barycentric(&(a as f32), &(b as f32), &(c as f32), weights) as u8
+        };
+        Color::new(
+            mix(a.r, b.r, c.r),
+            mix(a.g, b.g, c.g),
+            mix(a.b, b.b, c.b),
+            mix(a.a, b.a, c.a),
+        )
+    }
+}
+
+impl Barycentric for Vertex {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        Vertex {
+            position: Vector::barycentric(&a.position, &b.position, &c.position, weights),
+            normal: Vector::barycentric(&a.normal, &b.normal, &c.normal, weights),
+            u: f32::barycentric(&a.u, &b.u, &c.u, weights),
+            v: f32::barycentric(&a.v, &b.v, &c.v, weights),
+            color: Color::barycentric(&a.color, &b.color, &c.color, weights),
+            intensity: f32::barycentric(&a.intensity, &b.intensity, &c.intensity, weights),
+            tangent: Vector::barycentric(&a.tangent, &b.tangent, &c.tangent, weights),
+        }
+    }
+}
+
 impl Interpolate for Vector {
     fn interpolate(&self, other: &Self, factor: f32) -> Self {
         let x = self.x.interpolate(&other.x, factor);
@@ -15,44 +64,17 @@ impl Interpolate for Vector {
     }
 }
 
+/// 线性插值, 用于裁剪空间中切分三角形. 屏幕空间的透视校正由光栅化负责
 impl Interpolate for Vertex {
     fn interpolate(&self, other: &Self, factor: f32) -> Self {
-        let Vertex {
-            position: p1,
-            normal: n1,
-            u: u1,
-            v: v1,
-            color: c1,
-            intensity: i1,
-        } = self;
-        let Vertex {
-            position: p2,
-            normal: n2,
-            u: u2,
-            v: v2,
-            color: c2,
-            intensity: i2,
-        } = other;
-
-        let (u1, v1) = (u1 * p1.w, v1 * p1.w);
-        let (u2, v2) = (u2 * p2.w, v2 * p2.w);
-        let i1 = i1 * p1.w;
-        let i2 = i2 * p2.w;
-        let position = p1.interpolate(p2, factor);
-        let normal = n1.interpolate(n2, factor);
-        let u = u1.interpolate(&u2, factor) / position.w;
-        let v = v1.interpolate(&v2, factor) / position.w;
-        let intensity = i1.interpolate(&i2, factor) / position.w;
-        // 也应当考虑透视校正
-        let color = c1.interpolate(&c2, factor);
-
         Vertex {
-            position,
-            normal,
-            u,
-            v,
-            color,
-            intensity,
+            position: self.position.interpolate(&other.position, factor),
+            normal: self.normal.interpolate(&other.normal, factor),
+            u: self.u.interpolate(&other.u, factor),
+            v: self.v.interpolate(&other.v, factor),
+            color: self.color.interpolate(&other.color, factor),
+            intensity: self.intensity.interpolate(&other.intensity, factor),
+            tangent: self.tangent.interpolate(&other.tangent, factor),
         }
     }
 }
@@ -75,7 +97,7 @@ impl Interpolate for f32 {
 
 impl Interpolate for u8 {
     fn interpolate(&self, other: &Self, factor: f32) -> Self {
-        let x = *self as f32 + (other - self) as f32 * factor;
+        let x = *self as f32 + (*other as f32 - *self as f32) * factor;
         x as u8
     }
 }
@@ -0,0 +1,49 @@
+//! renderer-rs 是从零实现的软件光栅化渲染器.
+//!
+//! 渲染结果写入内存中的 [`FrameBuffer`], 不依赖任何窗口系统;
+//! 开启 `window` feature 后可以用 SDL 窗口实时显示.
+pub mod camera;
+pub mod canvas;
+pub mod clipper;
+pub mod color;
+pub mod framebuffer;
+pub mod image;
+pub mod interpolate;
+pub mod light;
+pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod model;
+pub mod rasterizer;
+pub mod sequence;
+pub mod shader;
+pub mod shadow;
+pub mod tangent;
+pub mod texture;
+pub mod tiler;
+pub mod vector;
+pub mod vertex;
+#[cfg(feature = "window")]
+pub mod window;
+
+#[cfg(feature = "window")]
+pub use crate::window::Window;
+pub use crate::{
+    camera::Camera,
+    canvas::{Canvas, CullMode, FrontFace, RenderStats, ShadingModel},
+    color::Color,
+    framebuffer::FrameBuffer,
+    interpolate::{Barycentric, Interpolate},
+    light::{Attenuation, Light, LightKind},
+    matrix::Matrix,
+    mesh::{LoadReport, Mesh},
+    sequence::{Animation, Sequence},
+    shader::{
+        BlinnPhongShader, FragmentShader, GouraudShader, GouraudVarying, PhongVarying, Surface,
+        Uniforms, Varying, VertexShader,
+    },
+    shadow::{ShadowMap, ShadowOptions},
+    texture::Texture,
+    vector::Vector,
+    vertex::Vertex,
+};
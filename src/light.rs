@@ -0,0 +1,175 @@
+//! 光源. 颜色分量范围 0 到 1, 和强度相乘得到光照的颜色.
+//!
+//! 着色时对每个光源求出照射方向和强度, 所有光源的贡献相加.
+use crate::{shadow::ShadowOptions, vector::Vector};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// 平行光, direction 是光线照射的方向, 没有衰减
+    Directional { direction: Vector },
+    /// 点光源, 向所有方向照射
+    Point { position: Vector },
+    /// 聚光灯, 与 direction 的夹角小于 inner 时全亮, 大于 outer 时不亮, 之间平滑过渡.
+    /// 角度是圆锥的半角, 弧度
+    Spot {
+        position: Vector,
+        direction: Vector,
+        inner: f32,
+        outer: f32,
+    },
+}
+
+/// 距离衰减, 强度除以 constant + linear * d + quadratic * d²
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    /// 不衰减
+    pub const NONE: Attenuation = Attenuation {
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+    };
+
+    pub fn new(constant: f32, linear: f32, quadratic: f32) -> Self {
+        Attenuation {
+            constant,
+            linear,
+            quadratic,
+        }
+    }
+
+    pub fn factor(&self, distance: f32) -> f32 {
+        let d = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        if d > 0.0 {
+            1.0 / d
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Attenuation::NONE
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vector,
+    pub intensity: f32,
+    /// 平行光忽略衰减
+    pub attenuation: Attenuation,
+    /// 投射阴影时的阴影贴图参数, 需要先用 Canvas::render_shadows 渲染阴影贴图
+    pub shadow: Option<ShadowOptions>,
+}
+
+impl Light {
+    /// 白色, 强度为 1, 不衰减, 不投射阴影
+    pub fn new(kind: LightKind) -> Self {
+        Light {
+            kind,
+            color: Vector::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+            attenuation: Attenuation::NONE,
+            shadow: None,
+        }
+    }
+
+    pub fn directional(direction: Vector) -> Self {
+        Light::new(LightKind::Directional { direction })
+    }
+
+    pub fn point(position: Vector) -> Self {
+        Light::new(LightKind::Point { position })
+    }
+
+    pub fn spot(position: Vector, direction: Vector, inner: f32, outer: f32) -> Self {
+        Light::new(LightKind::Spot {
+            position,
+            direction,
+            inner,
+            outer,
+        })
+    }
+
+    /// 世界空间中的点 point 受到的光照: 指向光源的单位向量, 以及乘上衰减和聚光灯范围之后的强度
+    pub fn incident(&self, point: &Vector) -> (Vector, f32) {
+        let (position, direction, cone) = match self.kind {
+            LightKind::Directional { direction } => {
+                let l = direction.normalize();
+                return (Vector::new(-l.x, -l.y, -l.z), self.intensity);
+            }
+            LightKind::Point { position } => (position, None, None),
+            LightKind::Spot {
+                position,
+                direction,
+                inner,
+                outer,
+            } => (position, Some(direction), Some((inner, outer))),
+        };
+        let offset = &position - point;
+        let distance = offset.length();
+        let l = offset.normalize();
+        let mut strength = self.intensity * self.attenuation.factor(distance);
+        if let (Some(direction), Some((inner, outer))) = (direction, cone) {
+            let cos = -l.dot(&direction.normalize());
+            strength *= smoothstep(outer.cos(), inner.cos(), cos);
+        }
+        (l, strength)
+    }
+
+    /// 强度为 strength 时的 RGB 光照
+    pub fn radiance(&self, strength: f32) -> Vector {
+        let Vector { x, y, z, .. } = self.color;
+        Vector::new(x * strength, y * strength, z * strength)
+    }
+}
+
+/// x 在 edge0 和 edge1 之间时从 0 平滑过渡到 1
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incident() {
+        let point = Vector::new(0.0, 0.0, 0.0);
+        let (l, strength) = Light::directional(Vector::new(0.0, -2.0, 0.0)).incident(&point);
+        assert_eq!((l, strength), (Vector::new(0.0, 1.0, 0.0), 1.0));
+
+        let light = Light {
+            attenuation: Attenuation::new(1.0, 0.0, 0.25),
+            ..Light::point(Vector::new(0.0, 0.0, -2.0))
+        };
+        let (l, strength) = light.incident(&point);
+        assert_eq!(l, Vector::new(0.0, 0.0, -1.0));
+        assert!((strength - 0.5).abs() < 1e-6);
+
+        // 朝 +z 照射的聚光灯, 半角 0.2 以内全亮, 0.4 以外不亮
+        let spot = Light::spot(
+            Vector::new(0.0, 0.0, -10.0),
+            Vector::new(0.0, 0.0, 1.0),
+            0.2,
+            0.4,
+        );
+        let at = |x: f32| spot.incident(&Vector::new(x, 0.0, 0.0)).1;
+        assert_eq!(at(0.0), 1.0);
+        assert_eq!(at(10.0 * 0.5f32.tan()), 0.0);
+        let edge = at(10.0 * 0.3f32.tan());
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+    }
+}
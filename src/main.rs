@@ -1,20 +1,373 @@
-mod canvas;
-mod color;
-mod interpolate;
-mod matrix;
-mod mesh;
-mod texture;
-mod vector;
-mod vertex;
-mod window;
-use mesh::Mesh;
-use vector::Vector;
-use window::Window;
-
-pub fn main() {
-    let mut mesh = Mesh::load_obj("assets/ahri.obj", Some("assets/ahri.image")).unwrap();
-    mesh.set_position(Vector::new(0.0, 0.0, -10.0));
-    let mut window = Window::new("demo", 800, 600).unwrap();
+use anyhow::{anyhow, bail, Context, Result};
+use renderer_rs::{
+    Animation, BlinnPhongShader, Camera, Canvas, CullMode, FrontFace, Light, Mesh, Sequence,
+    ShadingModel, ShadowOptions, Texture, Vector,
+};
+use std::env;
+use std::f32::consts::PI;
+use std::path::Path;
+
+const USAGE: &str = "用法: renderer-rs [选项] [模型]
+      renderer-rs convert <图片>...
+
+模型可以是 .obj, .gltf, .glb, .ply, .stl 或 .gua3d 文件, 省略时显示内置的 ahri 模型.
+贴图可以是 .guaimage 文件, 或者 png, bmp, tga, 二进制 ppm 图片.
+convert 把图片转换成 guaimage 贴图, 写入同目录下同名的 .guaimage 文件
+
+选项:
+    -t, --texture <路径>       贴图文件
+    -s, --size <宽x高>         画面大小, 默认 800x600
+        --camera <x,y,z>       相机位置, 默认 0,0,-20
+        --target <x,y,z>       相机朝向的点, 默认 0,0,0
+        --fov <弧度>           垂直视角, 默认 0.8
+        --rotate <x,y,z>       模型旋转, 弧度, 默认 0,3.1416,0 (内置模型正面朝向 +z, 转过来面对相机)
+        --translate <x,y,z>    模型位置, 默认 0,0,-10
+        --cull <方式>          none: 不剔除 (默认), back: 剔除背面, front: 剔除正面
+        --front-face <方向>    正面三角形在屏幕上的环绕方向, cw (默认) 或 ccw
+        --light <光源>         添加光源, 可以重复, 默认是位于 2,2,-15 的白色点光源
+                               point:x,y,z 点光源, directional:x,y,z 平行光 (照射方向),
+                               spot:x,y,z:x,y,z 聚光灯 (位置和照射方向, 半角 0.3 到 0.5 弧度),
+                               后面可以接 :r,g,b 颜色 (0 到 1) 和 :强度, 如 point:0,5,-15:1,0.8,0.6:1.5
+        --shadows              所有光源投射阴影. 点光源在场景中间时
+                               只有朝向场景中心的方向有阴影
+        --shading <方式>       gouraud: 逐顶点漫反射 (默认), phong: 逐像素 Blinn-Phong 光照
+        --shininess <指数>     Blinn-Phong 高光指数, 默认 32, 材质有高光指数时以材质为准
+        --threads <线程数>     光栅化线程数, 默认为 CPU 核数
+        --stats                输出模型顶点数, 渲染结束后输出三角形统计
+    -o, --output <路径>        渲染一帧写入 png 或 gif 文件, 不打开窗口
+        --export <路径>        把模型写入 .obj, .gua3d 或 .stl 文件, 不渲染
+        --frames <帧数>        渲染动画序列. 输出 gif 时写入一张动画,
+                               否则每帧写入一张带编号的图片,
+                               输出路径中的 {} 会替换成帧号
+        --animate <方式>       turntable: 模型转一圈 (默认), orbit: 相机绕目标转一圈
+        --path <x,y,z;...>     相机依次经过这些点
+        --delay <毫秒>         gif 动画每帧时长, 默认 40
+        --loop <次数>          gif 动画循环次数, 0 表示无限循环 (默认)
+    -h, --help                 显示帮助";
+
+struct Options {
+    model: String,
+    texture: Option<String>,
+    width: u32,
+    height: u32,
+    camera: Camera,
+    rotation: Vector,
+    position: Vector,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    lights: Vec<Light>,
+    shadows: bool,
+    shading_model: ShadingModel,
+    threads: Option<usize>,
+    stats: bool,
+    output: Option<String>,
+    export: Option<String>,
+    frames: Option<usize>,
+    animation: Animation,
+    delay: u16,
+    repeat: u16,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            model: "assets/ahri.obj".to_string(),
+            texture: None,
+            width: 800,
+            height: 600,
+            camera: Camera::default(),
+            rotation: Vector::new(0.0, PI, 0.0),
+            position: Vector::new(0.0, 0.0, -10.0),
+            cull_mode: CullMode::None,
+            front_face: FrontFace::Clockwise,
+            lights: vec![],
+            shadows: false,
+            shading_model: ShadingModel::Gouraud,
+            threads: None,
+            stats: false,
+            output: None,
+            export: None,
+            frames: None,
+            animation: Animation::Turntable,
+            delay: 40,
+            repeat: 0,
+        }
+    }
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut options = Options::default();
+        let mut model = None;
+        let mut shininess = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("选项 {} 缺少参数", arg));
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-t" | "--texture" => options.texture = Some(value()?),
+                "-s" | "--size" => {
+                    let (w, h) = parse_size(&value()?)?;
+                    options.width = w;
+                    options.height = h;
+                }
+                "--camera" => options.camera.position = parse_vector(&value()?)?,
+                "--target" => options.camera.target = parse_vector(&value()?)?,
+                "--fov" => options.camera.fov = value()?.parse().context("视角格式错误")?,
+                "--rotate" => options.rotation = parse_vector(&value()?)?,
+                "--translate" => options.position = parse_vector(&value()?)?,
+                "--cull" => {
+                    options.cull_mode = match value()?.as_str() {
+                        "none" => CullMode::None,
+                        "back" => CullMode::Back,
+                        "front" => CullMode::Front,
+                        other => bail!("未知的剔除方式 {}", other),
+                    }
+                }
+                "--front-face" => {
+                    options.front_face = match value()?.as_str() {
+                        "cw" => FrontFace::Clockwise,
+                        "ccw" => FrontFace::CounterClockwise,
+                        other => bail!("未知的环绕方向 {}", other),
+                    }
+                }
+                "--light" => options.lights.push(parse_light(&value()?)?),
+                "--shadows" => options.shadows = true,
+                "--shading" => {
+                    options.shading_model = match value()?.as_str() {
+                        "gouraud" => ShadingModel::Gouraud,
+                        "phong" => ShadingModel::BlinnPhong(BlinnPhongShader::default()),
+                        other => bail!("未知的光照模型 {}", other),
+                    }
+                }
+                "--shininess" => shininess = Some(value()?.parse().context("高光指数格式错误")?),
+                "--threads" => options.threads = Some(value()?.parse().context("线程数格式错误")?),
+                "--stats" => options.stats = true,
+                "-o" | "--output" => options.output = Some(value()?),
+                "--export" => options.export = Some(value()?),
+                "--frames" => options.frames = Some(value()?.parse().context("帧数格式错误")?),
+                "--animate" => {
+                    options.animation = match value()?.as_str() {
+                        "turntable" => Animation::Turntable,
+                        "orbit" => Animation::Orbit,
+                        other => bail!("未知的动画方式 {}", other),
+                    }
+                }
+                "--delay" => options.delay = value()?.parse().context("帧时长格式错误")?,
+                "--loop" => options.repeat = value()?.parse().context("循环次数格式错误")?,
+                "--path" => {
+                    let points = value()?
+                        .split(';')
+                        .map(parse_vector)
+                        .collect::<Result<Vec<_>>>()?;
+                    options.animation = Animation::CameraPath(points);
+                }
+                _ if arg.starts_with('-') => bail!("未知选项 {}", arg),
+                _ if model.is_none() => model = Some(arg),
+                _ => bail!("多余的参数 {}", arg),
+            }
+        }
+        if let Some(shininess) = shininess {
+            match &mut options.shading_model {
+                ShadingModel::BlinnPhong(shader) => shader.shininess = shininess,
+                ShadingModel::Gouraud => bail!("--shininess 只能和 --shading phong 一起使用"),
+            }
+        }
+        match model {
+            Some(model) => options.model = model,
+            None if options.texture.is_none() => {
+                options.texture = Some("assets/ahri.image".to_string())
+            }
+            None => {}
+        }
+        Ok(Some(options))
+    }
+
+    /// --light 指定的光源, 没有指定时使用 defaults; --shadows 让所有光源投射阴影
+    fn lights(&self, defaults: &[Light]) -> Vec<Light> {
+        let lights = if self.lights.is_empty() {
+            defaults
+        } else {
+            &self.lights
+        };
+        lights
+            .iter()
+            .map(|light| Light {
+                shadow: light.shadow.or(self.shadows.then(ShadowOptions::default)),
+                ..*light
+            })
+            .collect()
+    }
+
+    fn load_mesh(&self) -> Result<Mesh> {
+        let mut mesh = Mesh::load(&self.model, self.texture.as_deref())
+            .with_context(|| format!("读取模型 {} 失败", self.model))?;
+        for warning in &mesh.report.warnings {
+            eprintln!("警告: {}", warning);
+        }
+        if self.stats {
+            print!("顶点 {}", mesh.vertices.len());
+            if let Some(source) = mesh.report.source_vertices {
+                print!(" (合并前 {})", source);
+            }
+            println!(", 三角形 {}", mesh.indices.len());
+        }
+        mesh.rotation = self.rotation;
+        mesh.set_position(self.position);
+        Ok(mesh)
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let (w, h) = s
+        .split_once('x')
+        .ok_or_else(|| anyhow!("画面大小格式应为 宽x高: {}", s))?;
+    let w = w.trim().parse()?;
+    let h = h.trim().parse()?;
+    if w == 0 || h == 0 {
+        bail!("画面大小不能为 0: {}", s);
+    }
+    Ok((w, h))
+}
+
+fn parse_vector(s: &str) -> Result<Vector> {
+    let v = s
+        .split(',')
+        .map(|a| a.trim().parse())
+        .collect::<Result<Vec<f32>, _>>()
+        .with_context(|| format!("向量格式错误: {}", s))?;
+    match v[..] {
+        [x, y, z] => Ok(Vector::new(x, y, z)),
+        _ => bail!("向量格式应为 x,y,z: {}", s),
+    }
+}
+
+/// 类型:位置或方向[:方向][:颜色][:强度]
+fn parse_light(s: &str) -> Result<Light> {
+    let fields: Vec<&str> = s.split(':').collect();
+    let vector = |i: usize| -> Result<Vector> {
+        let field = fields
+            .get(i)
+            .ok_or_else(|| anyhow!("光源缺少参数: {}", s))?;
+        parse_vector(field)
+    };
+    let (mut light, rest) = match fields[0] {
+        "point" => (Light::point(vector(1)?), 2),
+        "directional" => (Light::directional(vector(1)?), 2),
+        "spot" => (Light::spot(vector(1)?, vector(2)?, 0.3, 0.5), 3),
+        other => bail!("未知的光源类型 {}", other),
+    };
+    match fields[rest..] {
+        [] => {}
+        [color] => light.color = parse_vector(color)?,
+        [color, intensity] => {
+            light.color = parse_vector(color)?;
+            light.intensity = intensity
+                .parse()
+                .with_context(|| format!("光源强度格式错误: {}", intensity))?;
+        }
+        _ => bail!("光源参数过多: {}", s),
+    }
+    Ok(light)
+}
+
+fn render_image(options: &Options, output: &str) -> Result<()> {
+    let mut mesh = options.load_mesh()?;
+    let mut canvas = Canvas::new(options.width, options.height);
+    canvas.set_camera(options.camera);
+    canvas.set_cull_mode(options.cull_mode);
+    canvas.set_front_face(options.front_face);
+    canvas.set_shading_model(options.shading_model);
+    canvas.set_lights(options.lights(canvas.lights()));
+    if let Some(threads) = options.threads {
+        canvas.set_threads(threads);
+    }
+    match options.frames {
+        Some(frames) => {
+            let sequence = Sequence::new(frames, options.animation.clone());
+            if output.to_ascii_lowercase().ends_with(".gif") {
+                // gif 的帧时长以 1/100 秒为单位, 四舍五入时不能先加 5, 否则可能溢出
+                let delay = options.delay / 10 + u16::from(options.delay % 10 >= 5);
+                sequence.save_gif(&mut canvas, &mut mesh, output, delay, Some(options.repeat))
+            } else {
+                sequence.save_images(&mut canvas, &mut mesh, output)
+            }
+        }
+        None => {
+            canvas.clear();
+            if canvas.casts_shadows() {
+                canvas.render_shadows(&[&mesh]);
+            }
+            canvas.draw_mesh(&mut mesh);
+            if options.stats {
+                let stats = canvas.stats();
+                println!(
+                    "三角形 {}, 裁剪 {}, 剔除 {}",
+                    stats.triangles, stats.clipped, stats.culled
+                );
+            }
+            canvas.framebuffer().save(output)
+        }
+    }
+}
+
+#[cfg(feature = "window")]
+fn run_window(options: &Options) -> Result<()> {
+    let mesh = options.load_mesh()?;
+    let mut window = renderer_rs::Window::new(&options.model, options.width, options.height)?;
+    window.set_camera(options.camera);
+    window.set_cull_mode(options.cull_mode);
+    window.set_front_face(options.front_face);
+    window.set_shading_model(options.shading_model);
+    window.set_lights(options.lights(window.lights()));
     window.set_mesh(mesh);
-    window.run();
+    window.run()
+}
+
+#[cfg(not(feature = "window"))]
+fn run_window(_: &Options) -> Result<()> {
+    bail!("编译时没有开启 window feature, 请用 --output 输出图片")
+}
+
+fn convert(paths: impl Iterator<Item = String>) -> Result<()> {
+    let mut converted = 0;
+    for path in paths {
+        let texture = Texture::load(&path).with_context(|| format!("读取图片 {} 失败", path))?;
+        let output = Path::new(&path).with_extension("guaimage");
+        let output = output.to_string_lossy();
+        texture
+            .save_guaimage(&output)
+            .with_context(|| format!("写入贴图 {} 失败", output))?;
+        println!("{} -> {}", path, output);
+        converted += 1;
+    }
+    if converted == 0 {
+        bail!("convert 需要至少一个图片路径");
+    }
+    Ok(())
+}
+
+pub fn main() -> Result<()> {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("convert") {
+        args.next();
+        return convert(args);
+    }
+    let options = match Options::parse(args)? {
+        Some(options) => options,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    if let Some(export) = &options.export {
+        let mesh = options.load_mesh()?;
+        return mesh
+            .save(export)
+            .with_context(|| format!("写入模型 {} 失败", export));
+    }
+    match &options.output {
+        Some(output) => render_image(&options, output),
+        None if options.frames.is_some() => bail!("渲染动画序列需要用 --output 指定输出路径"),
+        None => run_window(&options),
+    }
 }
@@ -0,0 +1,84 @@
+use crate::{
+    color::Color,
+    model::{gltf, mtl::Mtl},
+    texture::Texture,
+    vector::Vector,
+};
+use std::path::Path;
+
+/// 渲染用的材质, 颜色分量范围 0 到 1
+pub struct Material {
+    pub name: String,
+    /// 环境光反射率, 和漫反射颜色相乘
+    pub ambient: Vector,
+    pub diffuse: Vector,
+    pub specular: Vector,
+    pub shininess: f32,
+    pub opacity: f32,
+    /// 漫反射贴图
+    pub texture: Option<Texture>,
+    /// 高光贴图, 和 specular 相乘
+    pub specular_map: Option<Texture>,
+    /// 切线空间法线贴图, 需要顶点切线, 只在逐像素光照时使用
+    pub normal_map: Option<Texture>,
+}
+
+impl Material {
+    /// 贴图路径相对于 dir. 下载的模型经常缺少贴图, 读取失败的贴图记录在 warnings 中, 材质不使用它
+    pub fn from_mtl(mtl: &Mtl, dir: &Path, warnings: &mut Vec<String>) -> Self {
+        let mut load = |path: &Option<String>| -> Option<Texture> {
+            // 很多模型在 Windows 下导出, 路径分隔符是反斜杠
+            let path = dir.join(path.as_ref()?.replace('\\', "/"));
+            match Texture::load(&path.to_string_lossy()) {
+                Ok(texture) => Some(texture),
+                Err(e) => {
+                    warnings.push(format!(
+                        "读取材质 {} 的贴图 {} 失败: {:#}",
+                        mtl.name,
+                        path.display(),
+                        e
+                    ));
+                    None
+                }
+            }
+        };
+        Material {
+            name: mtl.name.clone(),
+            ambient: mtl.ambient,
+            diffuse: mtl.diffuse,
+            specular: mtl.specular,
+            shininess: mtl.shininess,
+            opacity: mtl.opacity,
+            texture: load(&mtl.diffuse_map),
+            specular_map: load(&mtl.specular_map),
+            normal_map: load(&mtl.bump_map),
+        }
+    }
+
+    /// glTF 只使用基础颜色和法线贴图, 其他参数保持默认
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let [r, g, b, a] = material.base_color;
+        Material {
+            name: material.name.clone(),
+            ambient: Vector::new(1.0, 1.0, 1.0),
+            diffuse: Vector::new(r, g, b),
+            specular: Vector::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            opacity: a,
+            texture: material.texture.as_ref().map(Texture::from_image),
+            specular_map: None,
+            normal_map: material.normal_texture.as_ref().map(Texture::from_image),
+        }
+    }
+
+    /// 贴图或顶点颜色乘上漫反射颜色和不透明度
+    pub fn modulate(&self, color: Color) -> Color {
+        let scale = |c: u8, k: f32| (c as f32 * k.clamp(0.0, 1.0)).round() as u8;
+        Color::new(
+            scale(color.r, self.diffuse.x),
+            scale(color.g, self.diffuse.y),
+            scale(color.b, self.diffuse.z),
+            scale(color.a, self.opacity),
+        )
+    }
+}
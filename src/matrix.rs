@@ -1,7 +1,7 @@
 use crate::vector::Vector;
 use std::ops::Mul;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Matrix {
     m: [f32; 16],
 }
@@ -15,6 +15,16 @@ impl Matrix {
         Self { m: [0.0; 16] }
     }
 
+    #[rustfmt::skip]
+    pub fn identity() -> Self {
+        Matrix::new([
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ])
+    }
+
     pub fn look_at_lh(eye: &Vector, target: &Vector, up: &Vector) -> Self {
         let axis_z = (target - eye).normalize();
         let axis_x = up.cross(&axis_z).normalize();
@@ -106,6 +116,68 @@ impl Matrix {
         Matrix::new(values)
     }
 
+    pub fn scaling(v: &Vector) -> Self {
+        let Vector { x, y, z, .. } = *v;
+        #[rustfmt::skip]
+        let values = [
+            x, 0.0, 0.0, 0.0,
+            0.0, y, 0.0, 0.0,
+            0.0, 0.0, z, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        Matrix::new(values)
+    }
+
+    /// 单位四元数 (x, y, z, w) 表示的旋转
+    pub fn rotation_quaternion(x: f32, y: f32, z: f32, w: f32) -> Self {
+        #[rustfmt::skip]
+        let values = [
+            1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y + z * w), 2.0 * (x * z - y * w), 0.0,
+            2.0 * (x * y - z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z + x * w), 0.0,
+            2.0 * (x * z + y * w), 2.0 * (y * z - x * w), 1.0 - 2.0 * (x * x + y * y), 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        Matrix::new(values)
+    }
+
+    /// look_at_lh 得到的视图矩阵只有旋转和平移, 相机位置是把平移部分用旋转的转置变换回去再取反
+    pub fn eye_position(&self) -> Vector {
+        let m = &self.m;
+        let t = Vector::new(m[12], m[13], m[14]);
+        let row = |i: usize| Vector::new(m[i * 4], m[i * 4 + 1], m[i * 4 + 2]);
+        Vector::new(-t.dot(&row(0)), -t.dot(&row(1)), -t.dot(&row(2)))
+    }
+
+    /// 左上角 3x3 部分的行列式, 小于 0 时变换会翻转三角形的环绕方向
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.m;
+        m[0] * (m[5] * m[10] - m[6] * m[9]) - m[1] * (m[4] * m[10] - m[6] * m[8])
+            + m[2] * (m[4] * m[9] - m[5] * m[8])
+    }
+
+    /// 变换法线并归一化. 法线要乘以逆矩阵的转置, 才能在非均匀缩放后仍然垂直于表面,
+    /// 这里用伴随矩阵代替逆矩阵, 两者只差一个行列式的倍数
+    pub fn transform_normal(&self, n: &Vector) -> Vector {
+        let m = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0 * 4 + c0] * m[r1 * 4 + c1] - m[r0 * 4 + c1] * m[r1 * 4 + c0]
+        };
+        // 行向量乘以伴随矩阵的转置, 即余子式矩阵
+        let x =
+            n.x * cofactor(1, 2, 1, 2) - n.y * cofactor(0, 2, 1, 2) + n.z * cofactor(0, 1, 1, 2);
+        let y =
+            -n.x * cofactor(1, 2, 0, 2) + n.y * cofactor(0, 2, 0, 2) - n.z * cofactor(0, 1, 0, 2);
+        let z =
+            n.x * cofactor(1, 2, 0, 1) - n.y * cofactor(0, 2, 0, 1) + n.z * cofactor(0, 1, 0, 1);
+        let v = Vector::new(x, y, z).normalize();
+        if self.determinant3() < 0.0 {
+            Vector::new(-v.x, -v.y, -v.z)
+        } else {
+            v
+        }
+    }
+
+    #[allow(clippy::identity_op, clippy::erasing_op)]
     pub fn transform(&self, v: &Vector) -> Vector {
         let m = self.m;
         let x = v.x * m[0 * 4 + 0] + v.y * m[1 * 4 + 0] + v.z * m[2 * 4 + 0] + m[3 * 4 + 0];
@@ -120,6 +192,28 @@ impl Matrix {
         }
     }
 
+    /// 变换到齐次坐标, 不做透视除法
+    #[allow(clippy::identity_op, clippy::erasing_op)]
+    pub fn transform_homogeneous(&self, v: &Vector) -> Vector {
+        let m = self.m;
+        let x = v.x * m[0 * 4 + 0] + v.y * m[1 * 4 + 0] + v.z * m[2 * 4 + 0] + m[3 * 4 + 0];
+        let y = v.x * m[0 * 4 + 1] + v.y * m[1 * 4 + 1] + v.z * m[2 * 4 + 1] + m[3 * 4 + 1];
+        let z = v.x * m[0 * 4 + 2] + v.y * m[1 * 4 + 2] + v.z * m[2 * 4 + 2] + m[3 * 4 + 2];
+        let w = v.x * m[0 * 4 + 3] + v.y * m[1 * 4 + 3] + v.z * m[2 * 4 + 3] + m[3 * 4 + 3];
+        Vector { x, y, z, w }
+    }
+
+    /// 变换方向, 只用左上角 3x3 部分, 不平移. w 保持不变
+    #[allow(clippy::identity_op, clippy::erasing_op)]
+    pub fn transform_direction(&self, v: &Vector) -> Vector {
+        let m = self.m;
+        let x = v.x * m[0 * 4 + 0] + v.y * m[1 * 4 + 0] + v.z * m[2 * 4 + 0];
+        let y = v.x * m[0 * 4 + 1] + v.y * m[1 * 4 + 1] + v.z * m[2 * 4 + 1];
+        let z = v.x * m[0 * 4 + 2] + v.y * m[1 * 4 + 2] + v.z * m[2 * 4 + 2];
+        Vector { x, y, z, w: v.w }
+    }
+
+    #[allow(clippy::identity_op, clippy::erasing_op)]
     pub fn transform_vector(&self, v: &Vector) -> Vector {
         let m = self.m;
         let x = v.x * m[0 * 4 + 0] + v.y * m[1 * 4 + 0] + v.z * m[2 * 4 + 0] + m[3 * 4 + 0];
@@ -131,6 +225,7 @@ impl Matrix {
 
 impl Mul for &Matrix {
     type Output = Matrix;
+    #[allow(clippy::needless_range_loop)]
     fn mul(self, rhs: Self) -> Self::Output {
         let mut values = [0.0; 16];
         let m1 = self.m;
@@ -149,6 +244,7 @@ impl Mul for &Matrix {
 
 impl Mul for Matrix {
     type Output = Matrix;
+    #[allow(clippy::op_ref)]
     fn mul(self, rhs: Self) -> Self::Output {
         &self * &rhs
     }
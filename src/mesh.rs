@@ -1,21 +1,108 @@
 use crate::color::Color;
-use crate::{texture::Texture, vector::Vector, vertex::Vertex};
-use anyhow::{anyhow, bail, Result};
-use std::fs::File;
+use crate::{
+    material::Material,
+    matrix::Matrix,
+    model::{gltf, mtl, obj, ply, stl},
+    tangent,
+    texture::Texture,
+    vector::Vector,
+    vertex::Vertex,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt::Write;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
 pub struct Mesh {
     pub position: Vector,
     pub rotation: Vector,
     pub vertices: Vec<Vertex>,
     pub indices: Vec<(usize, usize, usize)>,
+    /// 没有材质或材质没有漫反射贴图时使用的贴图
     pub texture: Option<Texture>,
+    pub materials: Vec<Material>,
+    pub submeshes: Vec<Submesh>,
+    /// 读取文件时的统计信息
+    pub report: LoadReport,
+}
+
+/// 读取模型时的统计信息, 库不输出任何内容, 由调用者决定是否显示
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadReport {
+    /// 合并重复顶点之前三角形引用的顶点数, 只有 obj 会合并顶点
+    pub source_vertices: Option<usize>,
+    /// 不影响读取的问题, 比如找不到材质库
+    pub warnings: Vec<String>,
+}
+
+/// 使用同一个材质的一段三角形
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    /// 在 Mesh::indices 中的范围
+    pub indices: Range<usize>,
+    /// Mesh::materials 的下标
+    pub material: Option<usize>,
 }
 
 impl Mesh {
+    /// 所有三角形组成一个没有材质的子网格
+    pub fn new(
+        vertices: Vec<Vertex>,
+        indices: Vec<(usize, usize, usize)>,
+        texture: Option<Texture>,
+    ) -> Self {
+        let submeshes = vec![Submesh {
+            indices: 0..indices.len(),
+            material: None,
+        }];
+        Mesh {
+            position: Vector::default(),
+            rotation: Vector::default(),
+            vertices,
+            indices,
+            texture,
+            materials: vec![],
+            submeshes,
+            report: LoadReport::default(),
+        }
+    }
+
     pub fn set_position(&mut self, position: Vector) {
         self.position = position;
     }
 
+    /// 模型空间到世界空间: 先旋转再平移
+    pub fn world_matrix(&self) -> Matrix {
+        Matrix::rotation(&self.rotation) * Matrix::translation(&self.position)
+    }
+
+    /// 生成顶点切线, 副切线方向不同的三角形共用的顶点会被复制
+    pub fn generate_tangents(&mut self) {
+        tangent::generate(&mut self.vertices, &mut self.indices);
+    }
+
+    /// 有法线贴图的材质需要切线
+    fn has_normal_map(&self) -> bool {
+        self.materials.iter().any(|m| m.normal_map.is_some())
+    }
+
+    /// 根据扩展名选择 obj, gltf/glb, ply, stl 或 gua3d 格式
+    pub fn load(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("obj") => Self::load_obj(path, texture_path),
+            Some("gltf" | "glb") => Self::load_gltf(path, texture_path),
+            Some("ply") => Self::load_ply(path, texture_path),
+            Some("stl") => Self::load_stl(path, texture_path),
+            Some("gua3d") => Self::load_gua3d(path, texture_path),
+            _ => bail!("不支持的模型格式: {}", path),
+        }
+    }
+
     pub fn load_gua3d(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
         let mut file = File::open(path)?;
         let mut content = String::new();
@@ -41,7 +128,6 @@ impl Mesh {
             .collect::<Vec<&str>>()[1]
             .trim()
             .parse()?;
-        println!("number of vertices {}", number_of_vertices);
         let number_of_indices: usize = lines
             .next()
             .ok_or(anyhow!("顶点索引格式错误"))?
@@ -49,7 +135,6 @@ impl Mesh {
             .collect::<Vec<&str>>()[1]
             .trim()
             .parse()?;
-        println!("number of indices {}", number_of_indices);
         let mut vertices = vec![];
         for _ in 0..number_of_vertices {
             let line = lines.next().ok_or(anyhow!("模型格式错误"))?;
@@ -69,7 +154,6 @@ impl Mesh {
                 _ => bail!("解析顶点数据错误"),
             }
         }
-        println!("read vertices {}", vertices.len());
         let mut indices = vec![];
         for _ in 0..number_of_indices {
             let line = lines.next().ok_or(anyhow!("模型格式错误"))?;
@@ -84,91 +168,260 @@ impl Mesh {
             }
         }
 
-        Ok(Mesh {
-            position: Vector::default(),
-            rotation: Vector::default(),
-            vertices,
-            indices,
-            texture,
-        })
+        Ok(Mesh::new(vertices, indices, texture))
     }
 
+    /// mtllib 引用的材质库和贴图路径都相对于所在文件的目录
     pub fn load_obj(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
-        let mut file = File::open(path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        // println!("mesh\n{}", content);
-
+        let content = fs::read_to_string(path)?;
+        let obj = obj::parse(&content)?;
         let texture = if let Some(path) = texture_path {
             Some(Texture::load(path)?)
         } else {
             None
         };
-        let lines = content.split('\n');
-        let mut positions = vec![];
-        let mut uvs = vec![];
-        let mut normals = vec![];
-        let mut indices = vec![];
-        let mut vertices = vec![];
+        let mut report = LoadReport {
+            source_vertices: Some(obj.indices.len() * 3),
+            ..LoadReport::default()
+        };
 
-        let mut index = 0;
-        for mut line in lines {
-            line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-            let vs: Vec<&str> = line.split(' ').collect();
-            match vs[0] {
-                "v" => {
-                    let x = vs[1].parse()?;
-                    let y = vs[2].parse()?;
-                    let z = vs[3].parse()?;
-                    let v = Vector::new(x, y, z);
-                    positions.push(v);
-                }
-                "vt" => {
-                    let u = vs[1].parse()?;
-                    let v = vs[2].parse()?;
-                    uvs.push((u, v));
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let mut definitions = vec![];
+        for library in &obj.libraries {
+            let library = dir.join(library.replace('\\', "/"));
+            // 导出的模型经常不带材质库, 这时仍然可以用 texture_path 指定的贴图
+            let content = match fs::read_to_string(&library) {
+                Ok(content) => content,
+                Err(e) => {
+                    let warning = format!("读取材质库 {} 失败: {}", library.display(), e);
+                    report.warnings.push(warning);
+                    continue;
                 }
-                "vn" => {
-                    let x = vs[1].parse()?;
-                    let y = vs[2].parse()?;
-                    let z = vs[3].parse()?;
-                    let v = Vector::new(x, y, z);
-                    normals.push(v);
+            };
+            let mtl_dir = library.parent().unwrap_or_else(|| Path::new(""));
+            let parsed = mtl::parse(&content)
+                .with_context(|| format!("解析材质库 {} 失败", library.display()))?;
+            for definition in parsed {
+                definitions.push((definition, mtl_dir.to_path_buf()));
+            }
+        }
+
+        // 只加载用到的材质
+        let mut materials = vec![];
+        let mut submeshes = vec![];
+        for group in obj.groups {
+            let definition = group
+                .material
+                .as_ref()
+                .and_then(|name| definitions.iter().find(|(d, _)| &d.name == name));
+            let material = match (definition, &group.material) {
+                (Some((definition, dir)), _) => {
+                    materials.push(Material::from_mtl(definition, dir, &mut report.warnings));
+                    Some(materials.len() - 1)
                 }
-                "f" => {
-                    let parse = |s: &str| -> Result<Vertex, std::num::ParseIntError> {
-                        let vs: Vec<usize> =
-                            s.split('/')
-                                .map(|s| s.parse())
-                                .collect::<Result<Vec<usize>, _>>()?;
-                        let p = positions[vs[0] - 1];
-                        let n = normals[vs[2] - 1];
-                        let (u, v) = uvs[vs[1] - 1];
-                        let vertex = Vertex::new(p, n, u, v, Color::new(255, 255, 255, 255));
-                        Ok(vertex)
-                    };
-                    let v1 = parse(vs[1])?;
-                    let v2 = parse(vs[2])?;
-                    let v3 = parse(vs[3])?;
-                    vertices.extend(vec![v1, v2, v3]);
-                    indices.push((index, index + 1, index + 2));
-                    index += 3;
-                    // println!("{} {} {}", positions.len(), normals.len(), uvs.len());
+                (None, Some(name)) => {
+                    report.warnings.push(format!("找不到材质 {}", name));
+                    None
                 }
-                _ => {}
-            }
+                (None, None) => None,
+            };
+            submeshes.push(Submesh {
+                indices: group.indices,
+                material,
+            });
         }
 
-        // println!("{} {}", vertices.len(), indices.len());
-        Ok(Mesh {
-            position: Vector::default(),
-            rotation: Vector::default(),
-            vertices,
-            indices,
-            texture,
-        })
+        let mut mesh = Mesh {
+            materials,
+            submeshes,
+            report,
+            ..Mesh::new(obj.vertices, obj.indices, texture)
+        };
+        if mesh.has_normal_map() {
+            mesh.generate_tangents();
+        }
+        Ok(mesh)
+    }
+
+    /// 每个 glTF 图元对应一个子网格, 节点变换已经烘焙到顶点上
+    pub fn load_gltf(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
+        let gltf = gltf::load(path)?;
+        let texture = if let Some(path) = texture_path {
+            Some(Texture::load(path)?)
+        } else {
+            None
+        };
+        let materials = gltf.materials.iter().map(Material::from_gltf).collect();
+        let submeshes = gltf
+            .primitives
+            .into_iter()
+            .map(|p| Submesh {
+                indices: p.indices,
+                material: p.material,
+            })
+            .collect();
+        let report = LoadReport {
+            warnings: gltf.warnings,
+            ..LoadReport::default()
+        };
+        let mut mesh = Mesh {
+            materials,
+            submeshes,
+            report,
+            ..Mesh::new(gltf.vertices, gltf.indices, texture)
+        };
+        if mesh.has_normal_map() {
+            mesh.generate_tangents();
+        }
+        Ok(mesh)
+    }
+
+    /// 根据扩展名写成 obj, gua3d 或二进制 stl 格式, 只写入几何数据, 不写入材质和贴图
+    pub fn save(&self, path: &str) -> Result<()> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("obj") => self.save_obj(path),
+            Some("gua3d") => self.save_gua3d(path),
+            Some("stl") => self.save_stl(path),
+            _ => bail!("不支持写入的模型格式: {}", path),
+        }
+    }
+
+    pub fn save_obj(&self, path: &str) -> Result<()> {
+        fs::write(path, obj::write(&self.vertices, &self.indices))?;
+        Ok(())
+    }
+
+    /// load_gua3d 读取的 version 1.1 格式, 每行一个顶点 x y z nx ny nz u v, 然后每行一个三角形
+    pub fn save_gua3d(&self, path: &str) -> Result<()> {
+        let mut content = String::new();
+        content.push_str("gua3d\nversion 1.1\n");
+        writeln!(content, "vertices {}", self.vertices.len())?;
+        writeln!(content, "triangles {}", self.indices.len())?;
+        for v in &self.vertices {
+            // {:?} 把 0 写成 0.0, 和生成 assets 中模型的导出脚本一致
+            let (p, n) = (v.position, v.normal);
+            writeln!(
+                content,
+                "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+                p.x, p.y, p.z, n.x, n.y, n.z, v.u, v.v
+            )?;
+        }
+        for (i, j, k) in &self.indices {
+            writeln!(content, "{} {} {}", i, j, k)?;
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn save_stl(&self, path: &str) -> Result<()> {
+        fs::write(path, stl::write(&self.vertices, &self.indices))?;
+        Ok(())
+    }
+
+    /// 顶点颜色来自 vertex 元素的 red / green / blue / alpha 属性
+    pub fn load_ply(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
+        let ply = ply::parse(&fs::read(path)?)?;
+        let texture = if let Some(path) = texture_path {
+            Some(Texture::load(path)?)
+        } else {
+            None
+        };
+        Ok(Mesh::new(ply.vertices, ply.indices, texture))
+    }
+
+    /// 顶点法线使用面片法线, 没有 uv
+    pub fn load_stl(path: &str, texture_path: Option<&str>) -> Result<Mesh> {
+        let stl = stl::parse(&fs::read(path)?)?;
+        let texture = if let Some(path) = texture_path {
+            Some(Texture::load(path)?)
+        } else {
+            None
+        };
+        Ok(Mesh::new(stl.vertices, stl.indices, texture))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obj_shares_vertices() {
+        let mesh = Mesh::load_obj("assets/ahri.obj", None).unwrap();
+        assert_eq!(mesh.indices.len(), 9494);
+        assert_eq!(mesh.vertices.len(), 6168);
+        assert_eq!(mesh.report.source_vertices, Some(9494 * 3));
+        assert_eq!(mesh.submeshes.len(), 1);
+    }
+
+    #[test]
+    fn test_obj_materials() {
+        // 同时运行的多个测试进程不能共用目录
+        let dir = std::env::temp_dir().join(format!("renderer-rs-mtl-{}", std::process::id()));
+        fs::create_dir_all(dir.join("textures")).unwrap();
+        let texture = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/illidan.guaimage");
+        fs::copy(texture, dir.join("textures/skin.guaimage")).unwrap();
+        fs::write(
+            dir.join("model.mtl"),
+            "newmtl skin\nKd 1 0.5 0.5\nmap_Kd textures\\skin.guaimage\nmap_Ks missing.png\n\
+             newmtl unused\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("model.obj"),
+            "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+             usemtl skin\nf 1 2 3\nusemtl missing\nf 2 4 3\nusemtl skin\nf 1 3 4\n",
+        )
+        .unwrap();
+
+        let mesh = Mesh::load_obj(dir.join("model.obj").to_str().unwrap(), None).unwrap();
+        assert_eq!(mesh.materials.len(), 1);
+        assert_eq!(mesh.materials[0].name, "skin");
+        // 缺少的贴图和材质只给出警告
+        assert!(mesh.materials[0].specular_map.is_none());
+        assert_eq!(mesh.report.warnings.len(), 2);
+        assert!(mesh.report.warnings[0].contains("missing.png"));
+        assert_eq!(mesh.report.warnings[1], "找不到材质 missing");
+        assert!(mesh.materials[0].texture.is_some());
+        assert_eq!(
+            mesh.submeshes,
+            vec![
+                Submesh {
+                    indices: 0..2,
+                    material: Some(0)
+                },
+                Submesh {
+                    indices: 2..3,
+                    material: None
+                },
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let dir = std::env::temp_dir().join(format!("renderer-rs-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mesh = Mesh::load_gua3d("assets/illidan.gua3d", None).unwrap();
+        for name in ["model.gua3d", "model.obj", "model.stl"] {
+            let first = dir.join(name);
+            let second = dir.join(format!("again.{}", name));
+            let (first, second) = (first.to_str().unwrap(), second.to_str().unwrap());
+            mesh.save(first).unwrap();
+            let loaded = Mesh::load(first, None).unwrap();
+            assert_eq!(loaded.indices.len(), mesh.indices.len(), "{}", name);
+            loaded.save(second).unwrap();
+            assert!(
+                fs::read(first).unwrap() == fs::read(second).unwrap(),
+                "{}",
+                name
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
     }
 }
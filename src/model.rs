@@ -0,0 +1,7 @@
+//! 模型文件解析, 全部在 crate 内实现, 不依赖外部库
+pub mod gltf;
+mod json;
+pub mod mtl;
+pub mod obj;
+pub mod ply;
+pub mod stl;
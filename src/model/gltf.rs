@@ -0,0 +1,900 @@
+//! glTF 2.0 解析, 支持 .gltf (外部文件或 data URI 缓冲) 和 .glb.
+//!
+//! 读取默认场景中所有节点的网格, 节点变换直接烘焙到顶点上, 合并成一个网格.
+//! 顶点属性支持 POSITION / NORMAL / TEXCOORD_0 / COLOR_0, 材质只读取基础颜色, 基础颜色贴图和法线贴图.
+//! 贴图支持 png, bmp, tga 和 ppm, 无法解码的贴图会被忽略并记录在 Gltf::warnings 中.
+use super::json::Json;
+use crate::{color::Color, image::RgbaImage, matrix::Matrix, vector::Vector, vertex::Vertex};
+use anyhow::{anyhow, bail, Context, Result};
+use std::convert::TryInto;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+/// 节点层级的上限, 防止循环引用
+const MAX_NODE_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, Default)]
+pub struct Gltf {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<(usize, usize, usize)>,
+    pub primitives: Vec<Primitive>,
+    pub materials: Vec<Material>,
+    /// 不影响读取的问题, 比如无法解码的贴图
+    pub warnings: Vec<String>,
+}
+
+/// 一个 glTF 图元对应的三角形
+#[derive(Clone, Debug, PartialEq)]
+pub struct Primitive {
+    /// Gltf::materials 的下标
+    pub material: Option<usize>,
+    /// 在 Gltf::indices 中的范围
+    pub indices: Range<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// baseColorFactor, RGBA 范围 0 到 1
+    pub base_color: [f32; 4],
+    /// baseColorTexture 引用的图片, 从上到下存放
+    pub texture: Option<RgbaImage>,
+    /// normalTexture 引用的切线空间法线贴图
+    pub normal_texture: Option<RgbaImage>,
+}
+
+/// 读取文件, 外部缓冲和图片的路径相对于文件所在目录
+pub fn load(path: &str) -> Result<Gltf> {
+    let data = fs::read(path)?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    parse(&data, dir)
+}
+
+pub fn parse(data: &[u8], dir: &Path) -> Result<Gltf> {
+    let (text, bin) = if data.starts_with(GLB_MAGIC) {
+        split_glb(data)?
+    } else {
+        (data, None)
+    };
+    let text = std::str::from_utf8(text).context("gltf 的 JSON 部分不是 UTF-8")?;
+    let json = Json::parse(text)?;
+    let version = json
+        .get("asset")
+        .and_then(|a| a.get("version"))
+        .and_then(Json::as_str)
+        .unwrap_or_default();
+    if !version.starts_with("2.") {
+        bail!("只支持 glTF 2.0, 文件版本为 {:?}", version);
+    }
+
+    let buffers = list(&json, "buffers")
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| {
+            load_buffer(buffer, i, bin, dir).with_context(|| format!("读取 buffers[{}] 失败", i))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let document = Document {
+        json: &json,
+        buffers,
+        dir,
+    };
+
+    let mut gltf = Gltf::default();
+    gltf.materials = document.materials(&mut gltf.warnings)?;
+    for root in document.scene_roots()? {
+        document.add_node(&mut gltf, root, &Matrix::identity(), 0)?;
+    }
+    Ok(gltf)
+}
+
+/// glb: 12 字节文件头, 之后是 JSON 块和可选的二进制块
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let word = |offset: usize| -> Result<u32> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("glb 数据不完整"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    if word(4)? != 2 {
+        bail!("只支持版本 2 的 glb");
+    }
+    let length = (word(8)? as usize).min(data.len());
+    let mut offset = 12;
+    let mut json = None;
+    let mut bin = None;
+    while offset + 8 <= length {
+        let chunk_length = word(offset)? as usize;
+        let kind = word(offset + 4)?;
+        let chunk = data
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or_else(|| anyhow!("glb 数据块不完整"))?;
+        match kind {
+            CHUNK_JSON if json.is_none() => json = Some(chunk),
+            CHUNK_BIN if bin.is_none() => bin = Some(chunk),
+            _ => {}
+        }
+        // 数据块按 4 字节对齐
+        offset += 8 + chunk_length.div_ceil(4) * 4;
+    }
+    let json = json.ok_or_else(|| anyhow!("glb 缺少 JSON 数据块"))?;
+    Ok((json, bin))
+}
+
+fn load_buffer(buffer: &Json, index: usize, bin: Option<&[u8]>, dir: &Path) -> Result<Vec<u8>> {
+    let length = buffer
+        .get("byteLength")
+        .and_then(Json::as_usize)
+        .ok_or_else(|| anyhow!("缺少 byteLength"))?;
+    let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) => load_uri(uri, dir)?,
+        // glb 中第一个没有 uri 的缓冲就是二进制块
+        None if index == 0 => bin.ok_or_else(|| anyhow!("缺少 glb 二进制块"))?.to_vec(),
+        None => bail!("缺少 uri"),
+    };
+    if data.len() < length {
+        bail!("长度 {} 小于 byteLength {}", data.len(), length);
+    }
+    Ok(data)
+}
+
+/// data URI 或相对路径
+fn load_uri(uri: &str, dir: &Path) -> Result<Vec<u8>> {
+    if let Some(rest) = uri.strip_prefix("data:") {
+        let (header, data) = rest
+            .split_once(',')
+            .ok_or_else(|| anyhow!("data URI 格式错误"))?;
+        if !header.ends_with(";base64") {
+            bail!("data URI 只支持 base64 编码");
+        }
+        return base64_decode(data);
+    }
+    let path = dir.join(percent_decode(uri));
+    fs::read(&path).with_context(|| format!("读取 {} 失败", path.display()))
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => bail!("base64 数据中有无效字符 {:?}", c as char),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+/// URI 中的 %20 之类的转义
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn list<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+/// 按下标取 json[key][index]
+fn element<'a>(json: &'a Json, key: &str, index: usize) -> Result<&'a Json> {
+    list(json, key)
+        .get(index)
+        .ok_or_else(|| anyhow!("{}[{}] 不存在", key, index))
+}
+
+fn floats(json: Option<&Json>) -> Option<Vec<f32>> {
+    json?.as_array()?.iter().map(Json::as_f32).collect()
+}
+
+struct Document<'a> {
+    json: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    dir: &'a Path,
+}
+
+impl Document<'_> {
+    /// 默认场景的根节点; 没有场景时使用所有不是其他节点子节点的节点
+    fn scene_roots(&self) -> Result<Vec<usize>> {
+        let scenes = list(self.json, "scenes");
+        let scene = self.json.get("scene").and_then(Json::as_usize);
+        if let Some(scene) = scene.or((!scenes.is_empty()).then_some(0)) {
+            let scene = element(self.json, "scenes", scene)?;
+            return Ok(list(scene, "nodes")
+                .iter()
+                .filter_map(Json::as_usize)
+                .collect());
+        }
+        let nodes = list(self.json, "nodes");
+        let mut is_child = vec![false; nodes.len()];
+        for node in nodes {
+            for child in list(node, "children").iter().filter_map(Json::as_usize) {
+                if let Some(c) = is_child.get_mut(child) {
+                    *c = true;
+                }
+            }
+        }
+        Ok((0..nodes.len()).filter(|&i| !is_child[i]).collect())
+    }
+
+    fn add_node(&self, gltf: &mut Gltf, index: usize, parent: &Matrix, depth: usize) -> Result<()> {
+        if depth > MAX_NODE_DEPTH {
+            bail!("节点层级太深, 可能有循环引用");
+        }
+        let node = element(self.json, "nodes", index)?;
+        let world = node_matrix(node)? * *parent;
+        if let Some(mesh) = node.get("mesh").and_then(Json::as_usize) {
+            let mesh = element(self.json, "meshes", mesh)?;
+            for (i, primitive) in list(mesh, "primitives").iter().enumerate() {
+                self.add_primitive(gltf, primitive, &world)
+                    .with_context(|| format!("读取节点 {} 的第 {} 个图元失败", index, i))?;
+            }
+        }
+        for child in list(node, "children").iter().filter_map(Json::as_usize) {
+            self.add_node(gltf, child, &world, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn add_primitive(&self, gltf: &mut Gltf, primitive: &Json, world: &Matrix) -> Result<()> {
+        let mode = primitive.get("mode").and_then(Json::as_usize).unwrap_or(4);
+        if !(4..=6).contains(&mode) {
+            // 点和线没有面积, 不绘制
+            return Ok(());
+        }
+        let attributes = primitive
+            .get("attributes")
+            .ok_or_else(|| anyhow!("缺少 attributes"))?;
+        let attribute = |name: &str| -> Result<Option<(usize, Vec<f32>)>> {
+            match attributes.get(name).and_then(Json::as_usize) {
+                Some(accessor) => self
+                    .accessor(accessor)
+                    .map(Some)
+                    .with_context(|| format!("读取 {} 失败", name)),
+                None => Ok(None),
+            }
+        };
+        let (_, positions) = attribute("POSITION")?.ok_or_else(|| anyhow!("缺少 POSITION"))?;
+        let count = positions.len() / 3;
+        let normals = attribute("NORMAL")?;
+        let uvs = attribute("TEXCOORD_0")?;
+        let colors = attribute("COLOR_0")?;
+
+        let mut vertices = Vec::with_capacity(count);
+        for i in 0..count {
+            let p = &positions[i * 3..i * 3 + 3];
+            let position = world.transform(&Vector::new(p[0], p[1], p[2]));
+            let normal = match &normals {
+                Some((_, n)) if n.len() >= i * 3 + 3 => {
+                    world.transform_normal(&Vector::new(n[i * 3], n[i * 3 + 1], n[i * 3 + 2]))
+                }
+                _ => Vector::default(),
+            };
+            // glTF 的 v 轴向下, 贴图的 v = 0 在底部
+            let (u, v) = match &uvs {
+                Some((_, t)) if t.len() >= i * 2 + 2 => (t[i * 2], 1.0 - t[i * 2 + 1]),
+                _ => (0.0, 0.0),
+            };
+            let color = match &colors {
+                Some((components, c)) if c.len() >= (i + 1) * components => {
+                    let c = &c[i * components..(i + 1) * components];
+                    let alpha = if *components == 4 { c[3] } else { 1.0 };
+                    Color::new(to_byte(c[0]), to_byte(c[1]), to_byte(c[2]), to_byte(alpha))
+                }
+                _ => Color::new(255, 255, 255, 255),
+            };
+            vertices.push(Vertex::new(position, normal, u, v, color));
+        }
+
+        let indices: Vec<usize> = match primitive.get("indices").and_then(Json::as_usize) {
+            Some(accessor) => self.indices(accessor).context("读取 indices 失败")?,
+            None => (0..count).collect(),
+        };
+        if let Some(&i) = indices.iter().find(|&&i| i >= count) {
+            bail!("顶点索引 {} 超出范围 {}", i, count);
+        }
+        let mut triangles = match mode {
+            4 => indices
+                .chunks_exact(3)
+                .map(|t| (t[0], t[1], t[2]))
+                .collect(),
+            5 => (2..indices.len())
+                .map(|i| {
+                    // 三角形带中偶数个三角形的顶点顺序要交换, 保持环绕方向一致
+                    if i % 2 == 0 {
+                        (indices[i - 2], indices[i - 1], indices[i])
+                    } else {
+                        (indices[i - 1], indices[i - 2], indices[i])
+                    }
+                })
+                .collect(),
+            _ => (2..indices.len())
+                .map(|i| (indices[0], indices[i - 1], indices[i]))
+                .collect::<Vec<_>>(),
+        };
+        // 镜像变换会翻转环绕方向
+        if world.determinant3() < 0.0 {
+            for t in &mut triangles {
+                *t = (t.0, t.2, t.1);
+            }
+        }
+
+        let base = gltf.vertices.len();
+        let start = gltf.indices.len();
+        if normals.is_none() {
+            // 没有法线时按规范使用面法线, 每个三角形使用独立的顶点
+            for (a, b, c) in triangles {
+                let (a, b, c) = (vertices[a], vertices[b], vertices[c]);
+                let normal = (&b.position - &a.position).cross(&(&c.position - &a.position));
+                let normal = if normal.length() > 0.0 {
+                    normal.normalize()
+                } else {
+                    normal
+                };
+                let i = gltf.vertices.len();
+                for v in [a, b, c] {
+                    gltf.vertices.push(Vertex { normal, ..v });
+                }
+                gltf.indices.push((i, i + 1, i + 2));
+            }
+        } else {
+            gltf.vertices.extend(vertices);
+            gltf.indices.extend(
+                triangles
+                    .into_iter()
+                    .map(|(a, b, c)| (base + a, base + b, base + c)),
+            );
+        }
+        let material = primitive.get("material").and_then(Json::as_usize);
+        if let Some(m) = material {
+            if m >= gltf.materials.len() {
+                bail!("材质 {} 不存在", m);
+            }
+        }
+        gltf.primitives.push(Primitive {
+            material,
+            indices: start..gltf.indices.len(),
+        });
+        Ok(())
+    }
+
+    /// 访问器引用的数据. 元素个数来自文件, 这里确认所有元素都在 bufferView 范围内,
+    /// 之后按个数分配内存不会超过文件本身的大小
+    fn elements(&self, index: usize) -> Result<Elements<'_>> {
+        let accessor = element(self.json, "accessors", index)?;
+        let count = accessor
+            .get("count")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| anyhow!("accessors[{}] 缺少 count", index))?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            other => bail!("accessors[{}] 的类型 {:?} 无效", index, other),
+        };
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .unwrap_or_default();
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => bail!("accessors[{}] 的分量类型 {} 无效", index, component_type),
+        };
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        if accessor.get("sparse").is_some() {
+            bail!("不支持 sparse 访问器");
+        }
+
+        // 没有 bufferView 的访问器全是 0, 只有配合 sparse 才有意义
+        let view = accessor
+            .get("bufferView")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| anyhow!("accessors[{}] 缺少 bufferView", index))?;
+        let (data, stride) = self.buffer_view(view)?;
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let element_size = size * components;
+        let stride = stride.unwrap_or(element_size);
+        if stride < element_size {
+            bail!("accessors[{}] 的 byteStride {} 小于元素大小", index, stride);
+        }
+        let end = match count.checked_sub(1) {
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|n| n.checked_add(offset))
+                .and_then(|n| n.checked_add(element_size)),
+            None => Some(0),
+        };
+        if end.is_none_or(|end| end > data.len()) {
+            bail!("accessors[{}] 超出 bufferView 的范围", index);
+        }
+        Ok(Elements {
+            data,
+            offset,
+            stride,
+            count,
+            components,
+            component_type,
+            size,
+            normalized,
+        })
+    }
+
+    /// 读取访问器, 返回每个元素的分量个数和所有分量. 归一化的整数会转换到 0 到 1 或 -1 到 1
+    fn accessor(&self, index: usize) -> Result<(usize, Vec<f32>)> {
+        let elements = self.elements(index)?;
+        let normalized = elements.normalized;
+        let mut values = Vec::with_capacity(elements.count * elements.components);
+        for i in 0..elements.count {
+            for bytes in elements.get(i).chunks_exact(elements.size) {
+                let value = match elements.component_type {
+                    5120 => {
+                        let v = bytes[0] as i8 as f32;
+                        if normalized {
+                            (v / 127.0).max(-1.0)
+                        } else {
+                            v
+                        }
+                    }
+                    5121 => {
+                        let v = bytes[0] as f32;
+                        if normalized {
+                            v / 255.0
+                        } else {
+                            v
+                        }
+                    }
+                    5122 => {
+                        let v = i16::from_le_bytes([bytes[0], bytes[1]]) as f32;
+                        if normalized {
+                            (v / 32767.0).max(-1.0)
+                        } else {
+                            v
+                        }
+                    }
+                    5123 => {
+                        let v = u16::from_le_bytes([bytes[0], bytes[1]]) as f32;
+                        if normalized {
+                            v / 65535.0
+                        } else {
+                            v
+                        }
+                    }
+                    5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                };
+                values.push(value);
+            }
+        }
+        Ok((elements.components, values))
+    }
+
+    /// 读取顶点索引, 按整数读取, 不经过 f32 损失精度
+    fn indices(&self, index: usize) -> Result<Vec<usize>> {
+        let elements = self.elements(index)?;
+        if elements.components != 1 || ![5121, 5123, 5125].contains(&elements.component_type) {
+            bail!("accessors[{}] 不是无符号整数的顶点索引", index);
+        }
+        Ok((0..elements.count)
+            .map(|i| match *elements.get(i) {
+                [v] => v as usize,
+                [a, b] => u16::from_le_bytes([a, b]) as usize,
+                [a, b, c, d] => u32::from_le_bytes([a, b, c, d]) as usize,
+                _ => unreachable!(),
+            })
+            .collect())
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>)> {
+        let view = element(self.json, "bufferViews", index)?;
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|b| self.buffers.get(b))
+            .ok_or_else(|| anyhow!("bufferViews[{}] 引用的 buffer 不存在", index))?;
+        let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let length = view
+            .get("byteLength")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| anyhow!("bufferViews[{}] 缺少 byteLength", index))?;
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| anyhow!("bufferViews[{}] 超出 buffer 的范围", index))?;
+        let stride = view.get("byteStride").and_then(Json::as_usize);
+        Ok((data, stride))
+    }
+
+    fn materials(&self, warnings: &mut Vec<String>) -> Result<Vec<Material>> {
+        let mut images: Vec<Option<Option<RgbaImage>>> =
+            vec![None; list(self.json, "images").len()];
+        let mut materials = vec![];
+        for (i, material) in list(self.json, "materials").iter().enumerate() {
+            let name = material
+                .get("name")
+                .and_then(Json::as_str)
+                .map_or_else(|| format!("material{}", i), str::to_string);
+            let pbr = material.get("pbrMetallicRoughness");
+            let base_color = match floats(pbr.and_then(|p| p.get("baseColorFactor"))) {
+                Some(c) if c.len() == 4 => [c[0], c[1], c[2], c[3]],
+                _ => [1.0; 4],
+            };
+            let mut texture = |info: Option<&Json>| -> Result<Option<RgbaImage>> {
+                let source = info
+                    .and_then(|t| t.get("index"))
+                    .and_then(Json::as_usize)
+                    .map(|t| element(self.json, "textures", t))
+                    .transpose()?
+                    .and_then(|t| t.get("source"))
+                    .and_then(Json::as_usize);
+                let source = match source {
+                    Some(source) => source,
+                    None => return Ok(None),
+                };
+                let cached = images
+                    .get_mut(source)
+                    .ok_or_else(|| anyhow!("images[{}] 不存在", source))?;
+                if cached.is_none() {
+                    // 贴图解码失败时只记录警告, 模型仍然可以不带贴图显示
+                    *cached = Some(match self.image(source) {
+                        Ok(image) => Some(image),
+                        Err(e) => {
+                            warnings.push(format!("读取 images[{}] 失败: {:#}", source, e));
+                            None
+                        }
+                    });
+                }
+                Ok(cached.clone().flatten())
+            };
+            materials.push(Material {
+                name,
+                base_color,
+                texture: texture(pbr.and_then(|p| p.get("baseColorTexture")))?,
+                normal_texture: texture(material.get("normalTexture"))?,
+            });
+        }
+        Ok(materials)
+    }
+
+    fn image(&self, index: usize) -> Result<RgbaImage> {
+        let image = element(self.json, "images", index)?;
+        let uri = image.get("uri").and_then(Json::as_str);
+        let data = match (uri, image.get("bufferView").and_then(Json::as_usize)) {
+            (Some(uri), _) => load_uri(uri, self.dir)?,
+            (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+            (None, None) => bail!("图片缺少 uri 和 bufferView"),
+        };
+        // mimeType 可以省略, data URI 自己带有类型
+        let mime = image
+            .get("mimeType")
+            .and_then(Json::as_str)
+            .or_else(|| uri?.strip_prefix("data:")?.split([';', ',']).next())
+            .unwrap_or("未知类型");
+        if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            bail!("不支持 JPEG 图片 ({})", mime);
+        }
+        // tga 没有文件标识, 只能按外部文件的扩展名判断
+        let extension = uri
+            .filter(|uri| !uri.starts_with("data:"))
+            .and_then(|uri| Path::new(uri).extension()?.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        crate::image::decode(&data, extension.as_deref())
+            .with_context(|| format!("解码 {} 图片失败", mime))
+    }
+}
+
+/// 访问器中的元素, 已经确认都在 data 的范围内
+struct Elements<'a> {
+    data: &'a [u8],
+    offset: usize,
+    stride: usize,
+    count: usize,
+    components: usize,
+    component_type: usize,
+    /// 每个分量的字节数
+    size: usize,
+    normalized: bool,
+}
+
+impl<'a> Elements<'a> {
+    /// 第 i 个元素所有分量的字节
+    fn get(&self, i: usize) -> &'a [u8] {
+        let start = self.offset + i * self.stride;
+        &self.data[start..start + self.size * self.components]
+    }
+}
+
+/// 节点的局部变换, matrix 按列存放, 正好是行向量约定下的矩阵
+fn node_matrix(node: &Json) -> Result<Matrix> {
+    if let Some(m) = floats(node.get("matrix")) {
+        let values: [f32; 16] = m
+            .try_into()
+            .map_err(|_| anyhow!("节点的 matrix 应有 16 个数"))?;
+        return Ok(Matrix::new(values));
+    }
+    let vector = |key: &str, default: [f32; 4]| match floats(node.get(key)) {
+        Some(v) if v.len() >= 3 => [v[0], v[1], v[2], v.get(3).copied().unwrap_or(0.0)],
+        _ => default,
+    };
+    let [tx, ty, tz, _] = vector("translation", [0.0; 4]);
+    let [x, y, z, w] = vector("rotation", [0.0, 0.0, 0.0, 1.0]);
+    let [sx, sy, sz, _] = vector("scale", [1.0; 4]);
+    Ok(Matrix::scaling(&Vector::new(sx, sy, sz))
+        * Matrix::rotation_quaternion(x, y, z, w)
+        * Matrix::translation(&Vector::new(tx, ty, tz)))
+}
+
+fn to_byte(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::png;
+
+    fn base64_encode(data: &[u8]) -> String {
+        const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in data.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - i * 8));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(TABLE[(bits >> (18 - i * 6)) as usize & 0x3f] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    /// 一个四边形: 4 个 float 顶点, 4 个 uv, 6 个 u16 索引
+    fn quad_buffer() -> Vec<u8> {
+        let mut buffer = vec![];
+        for v in [
+            0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0,
+        ] {
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [0.0f32, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0] {
+            buffer.extend_from_slice(&v.to_le_bytes());
+        }
+        for i in [0u16, 1, 2, 0, 2, 3] {
+            buffer.extend_from_slice(&i.to_le_bytes());
+        }
+        buffer
+    }
+
+    /// 各参数会原样插入到对应的 JSON 位置, 用于添加 uri, 额外的 bufferView, 贴图等
+    fn quad_json(buffer: &str, views: &str, material: &str, rest: &str) -> String {
+        format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [
+                    {{"translation": [1, 0, 0], "children": [1]}},
+                    {{"scale": [2, 2, 2], "rotation": [0, 0, 0, 1], "mesh": 0}}
+                ],
+                "meshes": [{{"primitives": [{{
+                    "attributes": {{"POSITION": 0, "TEXCOORD_0": 1}},
+                    "indices": 2, "material": 0
+                }}]}}],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3"}},
+                    {{"bufferView": 0, "byteOffset": 48, "componentType": 5126, "count": 4, "type": "VEC2"}},
+                    {{"bufferView": 1, "componentType": 5123, "count": 6, "type": "SCALAR"}}
+                ],
+                "bufferViews": [
+                    {{"buffer": 0, "byteLength": 80}},
+                    {{"buffer": 0, "byteOffset": 80, "byteLength": 12}}
+                    {}
+                ],
+                "buffers": [{{"byteLength": 92 {}}}],
+                "materials": [{{
+                    "name": "quad",
+                    "pbrMetallicRoughness": {{"baseColorFactor": [1, 0.5, 0.5, 1] {}}}
+                }}]
+                {}
+            }}"#,
+            views, buffer, material, rest
+        )
+    }
+
+    #[test]
+    fn test_gltf_data_uri() {
+        let uri = format!(
+            r#", "uri": "data:application/octet-stream;base64,{}""#,
+            base64_encode(&quad_buffer())
+        );
+        let json = quad_json(&uri, "", "", "");
+        let gltf = parse(json.as_bytes(), Path::new("")).unwrap();
+        assert_eq!(gltf.indices, vec![(0, 1, 2), (3, 4, 5)]);
+        assert_eq!(gltf.vertices.len(), 6);
+        // 先缩放 2 倍再平移
+        assert_eq!(gltf.vertices[2].position, Vector::new(3.0, 2.0, 0.0));
+        // 没有法线时使用面法线, v 轴翻转
+        assert_eq!(gltf.vertices[0].normal, Vector::new(0.0, 0.0, 1.0));
+        assert_eq!((gltf.vertices[0].u, gltf.vertices[0].v), (0.0, 0.0));
+        assert_eq!((gltf.vertices[2].u, gltf.vertices[2].v), (1.0, 1.0));
+        assert_eq!(
+            gltf.primitives,
+            vec![Primitive {
+                material: Some(0),
+                indices: 0..2
+            }]
+        );
+        assert_eq!(gltf.materials[0].name, "quad");
+        assert_eq!(gltf.materials[0].base_color, [1.0, 0.5, 0.5, 1.0]);
+        assert!(gltf.materials[0].texture.is_none());
+        assert!(gltf.materials[0].normal_texture.is_none());
+        assert!(gltf.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_glb_embedded_texture() {
+        let image = [255, 0, 0, 255, 0, 255, 0, 255];
+        let png = png::encode(2, 1, &image).unwrap();
+        let mut bin = quad_buffer();
+        let png_offset = bin.len();
+        bin.extend_from_slice(&png);
+        while !bin.len().is_multiple_of(4) {
+            bin.push(0);
+        }
+        let view = format!(
+            r#", {{"buffer": 0, "byteOffset": {}, "byteLength": {}}}"#,
+            png_offset,
+            png.len()
+        );
+        let material = r#", "baseColorTexture": {"index": 0}"#;
+        let rest = r#", "textures": [{"source": 0}],
+            "images": [{"bufferView": 2, "mimeType": "image/png"}]"#;
+        let mut json = quad_json("", &view, material, rest).into_bytes();
+        while !json.len().is_multiple_of(4) {
+            json.push(b' ');
+        }
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+
+        let gltf = parse(&glb, Path::new("")).unwrap();
+        assert_eq!(gltf.indices.len(), 2);
+        let texture = gltf.materials[0].texture.as_ref().unwrap();
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(texture.pixels, image);
+    }
+
+    #[test]
+    fn test_unsupported_image() {
+        let uri = format!(
+            r#", "uri": "data:application/octet-stream;base64,{}""#,
+            base64_encode(&quad_buffer())
+        );
+        let material = r#", "baseColorTexture": {"index": 0}"#;
+        // jpeg 文件以 FF D8 FF 开头
+        let rest = format!(
+            r#", "textures": [{{"source": 0}}],
+            "images": [{{"uri": "data:image/jpeg;base64,{}"}}]"#,
+            base64_encode(&[0xff, 0xd8, 0xff, 0xe0])
+        );
+        let json = quad_json(&uri, "", material, &rest);
+        let gltf = parse(json.as_bytes(), Path::new("")).unwrap();
+        assert!(gltf.materials[0].texture.is_none());
+        assert_eq!(gltf.warnings.len(), 1);
+        assert!(
+            gltf.warnings[0].contains("image/jpeg"),
+            "{}",
+            gltf.warnings[0]
+        );
+    }
+
+    #[test]
+    fn test_accessor_bounds() {
+        let json = Json::parse(
+            r#"{
+                "accessors": [
+                    {"bufferView": 0, "componentType": 5125, "count": 2, "type": "SCALAR"},
+                    {"bufferView": 1, "componentType": 5126, "count": 4611686018427387904, "type": "VEC3"},
+                    {"bufferView": 0, "componentType": 5126, "count": 1000000000000, "type": "VEC3"},
+                    {"componentType": 5126, "count": 1000000000000, "type": "VEC3"}
+                ],
+                "bufferViews": [
+                    {"buffer": 0, "byteLength": 8},
+                    {"buffer": 0, "byteLength": 8, "byteStride": 4},
+                    {"buffer": 0, "byteOffset": 18446744073709551615, "byteLength": 8}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let mut buffer = 16_777_217u32.to_le_bytes().to_vec();
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes());
+        let document = Document {
+            json: &json,
+            buffers: vec![buffer],
+            dir: Path::new(""),
+        };
+        // 超过 2^24 的索引也要精确
+        assert_eq!(
+            document.indices(0).unwrap(),
+            [16_777_217, u32::MAX as usize]
+        );
+        // byteStride 小于元素大小, count 超出数据, 没有 bufferView, bufferView 越界都不能分配内存
+        assert!(document.accessor(1).is_err());
+        assert!(document.accessor(2).is_err());
+        assert!(document.accessor(3).is_err());
+        assert!(document.buffer_view(2).is_err());
+    }
+
+    #[test]
+    fn test_node_matrix() {
+        let node = Json::parse(r#"{"matrix": [2,0,0,0, 0,2,0,0, 0,0,2,0, 1,2,3,1]}"#).unwrap();
+        let m = node_matrix(&node).unwrap();
+        assert_eq!(
+            m.transform(&Vector::new(1.0, 1.0, 1.0)),
+            Vector::new(3.0, 4.0, 5.0)
+        );
+        // 绕 z 轴转 90 度
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let node = Json::parse(&format!(r#"{{"rotation": [0, 0, {}, {}]}}"#, half, half)).unwrap();
+        let v = node_matrix(&node)
+            .unwrap()
+            .transform(&Vector::new(1.0, 0.0, 0.0));
+        assert!(v.x.abs() < 1e-6 && (v.y - 1.0).abs() < 1e-6, "{:?}", v);
+    }
+}
@@ -0,0 +1,181 @@
+//! Wavefront MTL 材质库解析. 只读取数值和贴图路径, 贴图由调用方加载
+use crate::vector::Vector;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// 一个 newmtl 定义的材质, 颜色分量范围 0 到 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mtl {
+    pub name: String,
+    /// Ka, 没有时为 1, 环境光和漫反射颜色相同
+    pub ambient: Vector,
+    /// Kd
+    pub diffuse: Vector,
+    /// Ks
+    pub specular: Vector,
+    /// Ns
+    pub shininess: f32,
+    /// d, 或者 1 - Tr
+    pub opacity: f32,
+    /// map_Kd
+    pub diffuse_map: Option<String>,
+    /// map_Ks
+    pub specular_map: Option<String>,
+    /// map_Bump / bump / norm
+    pub bump_map: Option<String>,
+}
+
+impl Mtl {
+    pub fn new(name: &str) -> Self {
+        Mtl {
+            name: name.to_string(),
+            ambient: Vector::new(1.0, 1.0, 1.0),
+            diffuse: Vector::new(1.0, 1.0, 1.0),
+            specular: Vector::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            opacity: 1.0,
+            diffuse_map: None,
+            specular_map: None,
+            bump_map: None,
+        }
+    }
+}
+
+pub fn parse(content: &str) -> Result<Vec<Mtl>> {
+    let mut materials: Vec<Mtl> = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        let result = if keyword == "newmtl" {
+            match args[..] {
+                [] => Err(anyhow!("缺少材质名")),
+                _ => {
+                    materials.push(Mtl::new(&args.join(" ")));
+                    Ok(())
+                }
+            }
+        } else {
+            match materials.last_mut() {
+                Some(material) => parse_statement(material, keyword, &args),
+                None => Err(anyhow!("{} 出现在 newmtl 之前", keyword)),
+            }
+        };
+        result.with_context(|| format!("mtl 第 {} 行格式错误: {}", index + 1, line.trim()))?;
+    }
+    Ok(materials)
+}
+
+fn parse_statement(material: &mut Mtl, keyword: &str, args: &[&str]) -> Result<()> {
+    match keyword {
+        "Ka" => material.ambient = parse_color(args)?,
+        "Kd" => material.diffuse = parse_color(args)?,
+        "Ks" => material.specular = parse_color(args)?,
+        "Ns" => material.shininess = parse_float(args)?,
+        "d" => material.opacity = parse_float(args)?,
+        "Tr" => material.opacity = 1.0 - parse_float(args)?,
+        "map_Kd" => material.diffuse_map = Some(parse_map(args)?),
+        "map_Ks" => material.specular_map = Some(parse_map(args)?),
+        "map_Bump" | "map_bump" | "bump" | "norm" => material.bump_map = Some(parse_map(args)?),
+        // 其他光照模型参数和贴图暂时用不到
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_float(args: &[&str]) -> Result<f32> {
+    match args {
+        [v] => v.parse().map_err(|_| anyhow!("数字格式错误 {}", v)),
+        _ => bail!("应有 1 个数值"),
+    }
+}
+
+/// 颜色可以只写一个分量, 表示灰度
+fn parse_color(args: &[&str]) -> Result<Vector> {
+    let v = args
+        .iter()
+        .map(|s| s.parse().map_err(|_| anyhow!("数字格式错误 {}", s)))
+        .collect::<Result<Vec<f32>>>()?;
+    match v[..] {
+        [c] => Ok(Vector::new(c, c, c)),
+        [r, g, b] => Ok(Vector::new(r, g, b)),
+        _ => bail!("颜色应有 1 或 3 个分量"),
+    }
+}
+
+/// 跳过 -s 1 1 1 之类的贴图选项, 剩下的部分是文件名, 文件名中可以有空格
+fn parse_map(args: &[&str]) -> Result<String> {
+    let mut rest = args;
+    while let [option, tail @ ..] = rest {
+        if !option.starts_with('-') {
+            break;
+        }
+        let (min, max) = match *option {
+            "-o" | "-s" | "-t" => (1, 3),
+            "-mm" => (2, 2),
+            _ => (1, 1),
+        };
+        let count = tail
+            .iter()
+            .take(max)
+            .take_while(|s| s.parse::<f32>().is_ok())
+            .count()
+            .max(min);
+        if tail.len() < count {
+            bail!("贴图选项 {} 缺少参数", option);
+        }
+        rest = &tail[count..];
+    }
+    if rest.is_empty() {
+        bail!("缺少贴图文件名");
+    }
+    Ok(rest.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let materials = parse(
+            "# 两个材质\n\
+             newmtl body\n\
+             Ka 0.1 0.1 0.1\n\
+             Kd 0.8 0.5 0.2\n\
+             Ks 0.5\n\
+             Ns 32\n\
+             Tr 0.25\n\
+             map_Kd -s 1 1 1 -clamp on textures/body skin.png\n\
+             map_Bump -bm 0.4 body_n.png\n\
+             newmtl hair\n\
+             d 0.5\n\
+             map_Ks hair_s.png\n",
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 2);
+        let body = &materials[0];
+        assert_eq!(body.name, "body");
+        assert_eq!(body.ambient, Vector::new(0.1, 0.1, 0.1));
+        assert_eq!(body.diffuse, Vector::new(0.8, 0.5, 0.2));
+        assert_eq!(body.specular, Vector::new(0.5, 0.5, 0.5));
+        assert_eq!(body.shininess, 32.0);
+        assert_eq!(body.opacity, 0.75);
+        assert_eq!(body.diffuse_map.as_deref(), Some("textures/body skin.png"));
+        assert_eq!(body.bump_map.as_deref(), Some("body_n.png"));
+        let hair = &materials[1];
+        assert_eq!(hair.ambient, Vector::new(1.0, 1.0, 1.0));
+        assert_eq!(hair.opacity, 0.5);
+        assert_eq!(hair.diffuse_map, None);
+        assert_eq!(hair.specular_map.as_deref(), Some("hair_s.png"));
+
+        let error = parse("Kd 1 1 1\n").unwrap_err();
+        assert!(error.to_string().contains("第 1 行"), "{}", error);
+    }
+}
@@ -0,0 +1,448 @@
+//! Wavefront OBJ 解析, 支持几何部分: v / vt / vn / f 以及 o / g / s.
+//!
+//! 多边形按扇形切成三角形; 索引可以是负数, 表示相对当前已定义的元素倒数;
+//! 面可以省略 uv 或法线, 没有法线的顶点按光滑组生成法线. 三角形按 usemtl 指定的材质分组,
+//! 材质库由调用方读取. 其他语句 (线, 曲面等) 忽略.
+use crate::{color::Color, vector::Vector, vertex::Vertex};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// 解析出来的索引网格
+#[derive(Clone, Debug, Default)]
+pub struct Obj {
+    pub vertices: Vec<Vertex>,
+    /// 同一材质的三角形连续存放
+    pub indices: Vec<(usize, usize, usize)>,
+    /// mtllib 引用的材质库文件, 路径相对于 obj 文件
+    pub libraries: Vec<String>,
+    /// 按材质分组的三角形, 按材质第一次出现的顺序排列
+    pub groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    /// usemtl 指定的材质名, 第一个 usemtl 之前的面没有材质
+    pub material: Option<String>,
+    /// 在 Obj::indices 中的范围
+    pub indices: Range<usize>,
+}
+
+/// 顶点去重用的键: 位置, uv, 法线索引, 以及没有法线时生成法线所属的组
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+    group: Smoothing,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Smoothing {
+    /// 顶点带法线, 不需要生成
+    None,
+    /// 光滑组内共用位置的面平均法线
+    Group(u32),
+    /// 关闭光滑时每个面单独使用面法线
+    Face(usize),
+}
+
+#[derive(Default)]
+struct Parser {
+    positions: Vec<(Vector, Option<Color>)>,
+    uvs: Vec<(f32, f32)>,
+    normals: Vec<Vector>,
+    smoothing: u32,
+    faces: usize,
+    cache: HashMap<Key, usize>,
+    obj: Obj,
+    /// 按出现顺序排列的材质名, 和每个材质的三角形
+    materials: Vec<Option<String>>,
+    triangles: Vec<Vec<(usize, usize, usize)>>,
+    material: usize,
+    /// 需要生成法线的顶点累加的面法线
+    generated: Vec<Option<Vector>>,
+}
+
+pub fn parse(content: &str) -> Result<Obj> {
+    let mut parser = Parser {
+        smoothing: 1,
+        materials: vec![None],
+        triangles: vec![vec![]],
+        ..Parser::default()
+    };
+    let mut lines = content.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        // 行尾的反斜杠表示下一行是这一行的延续
+        let mut line = line.to_string();
+        while line.trim_end().ends_with('\\') {
+            let end = line.trim_end().len() - 1;
+            line.truncate(end);
+            line.push(' ');
+            match lines.next() {
+                Some((_, next)) => line.push_str(next),
+                None => break,
+            }
+        }
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => &line,
+        };
+        parser
+            .parse_line(line)
+            .with_context(|| format!("obj 第 {} 行格式错误: {}", number, line.trim()))?;
+    }
+    Ok(parser.finish())
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let args: Vec<&str> = words.collect();
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args)?;
+                // 扩展格式 v x y z r g b, 颜色范围 0 到 1
+                let color = match v[..] {
+                    [_, _, _, r, g, b] => Some(Color::new(to_byte(r), to_byte(g), to_byte(b), 255)),
+                    [_, _, _] | [_, _, _, _] => None,
+                    _ => bail!("顶点应有 3 个坐标"),
+                };
+                self.positions.push((Vector::new(v[0], v[1], v[2]), color));
+            }
+            "vt" => {
+                let v = parse_floats(&args)?;
+                match v[..] {
+                    [u] => self.uvs.push((u, 0.0)),
+                    [u, v] | [u, v, _] => self.uvs.push((u, v)),
+                    _ => bail!("uv 应有 1 到 3 个分量"),
+                }
+            }
+            "vn" => match parse_floats(&args)?[..] {
+                [x, y, z] => self.normals.push(Vector::new(x, y, z)),
+                _ => bail!("法线应有 3 个分量"),
+            },
+            "f" => {
+                if args.len() < 3 {
+                    bail!("面至少需要 3 个顶点");
+                }
+                let keys = args
+                    .iter()
+                    .map(|s| self.parse_corner(s))
+                    .collect::<Result<Vec<_>>>()?;
+                self.add_face(&keys);
+            }
+            "s" => {
+                self.smoothing = match args.first().copied() {
+                    Some("off") => 0,
+                    Some(group) => group.parse().context("光滑组格式错误")?,
+                    None => bail!("缺少光滑组"),
+                }
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    bail!("缺少材质库文件名");
+                }
+                self.obj
+                    .libraries
+                    .extend(args.iter().map(|s| s.to_string()));
+            }
+            "usemtl" => {
+                if args.is_empty() {
+                    bail!("缺少材质名");
+                }
+                let name = Some(args.join(" "));
+                self.material = match self.materials.iter().position(|m| *m == name) {
+                    Some(i) => i,
+                    None => {
+                        self.materials.push(name);
+                        self.triangles.push(vec![]);
+                        self.materials.len() - 1
+                    }
+                };
+            }
+            // o / g 只用来组织面, 不影响几何; 线, 曲面等语句忽略
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 解析 v, v/vt, v//vn, v/vt/vn 形式的顶点
+    fn parse_corner(&self, s: &str) -> Result<Key> {
+        let mut parts = s.split('/');
+        let position = parts.next().unwrap_or_default();
+        let uv = parts.next().filter(|s| !s.is_empty());
+        let normal = parts.next().filter(|s| !s.is_empty());
+        if parts.next().is_some() {
+            bail!("面的顶点格式错误 {}", s);
+        }
+        let position = resolve(position, self.positions.len())?;
+        let uv = uv.map(|s| resolve(s, self.uvs.len())).transpose()?;
+        let normal = normal.map(|s| resolve(s, self.normals.len())).transpose()?;
+        let group = if normal.is_some() {
+            Smoothing::None
+        } else if self.smoothing == 0 {
+            Smoothing::Face(self.faces)
+        } else {
+            Smoothing::Group(self.smoothing)
+        };
+        Ok(Key {
+            position,
+            uv,
+            normal,
+            group,
+        })
+    }
+
+    fn add_face(&mut self, keys: &[Key]) {
+        let indices: Vec<usize> = keys.iter().map(|key| self.vertex(key)).collect();
+        // 多边形按扇形切开
+        let triangles = &mut self.triangles[self.material];
+        for i in 1..indices.len() - 1 {
+            triangles.push((indices[0], indices[i], indices[i + 1]));
+        }
+
+        // 面法线用 Newell 方法计算, 对非平面的多边形也比较稳定, 长度正比于面积
+        let mut normal = Vector::default();
+        for (i, key) in keys.iter().enumerate() {
+            let a = self.positions[key.position].0;
+            let b = self.positions[keys[(i + 1) % keys.len()].position].0;
+            normal.x += (a.y - b.y) * (a.z + b.z);
+            normal.y += (a.z - b.z) * (a.x + b.x);
+            normal.z += (a.x - b.x) * (a.y + b.y);
+        }
+        for &i in &indices {
+            if let Some(n) = &mut self.generated[i] {
+                n.x += normal.x;
+                n.y += normal.y;
+                n.z += normal.z;
+            }
+        }
+        self.faces += 1;
+    }
+
+    /// 同一个键只生成一个顶点
+    fn vertex(&mut self, key: &Key) -> usize {
+        if let Some(&index) = self.cache.get(key) {
+            return index;
+        }
+        let (position, color) = self.positions[key.position];
+        let (u, v) = key.uv.map_or((0.0, 0.0), |i| self.uvs[i]);
+        let normal = key.normal.map(|i| self.normals[i]);
+        let color = color.unwrap_or(Color::new(255, 255, 255, 255));
+        let vertex = Vertex::new(position, normal.unwrap_or_default(), u, v, color);
+        let index = self.obj.vertices.len();
+        self.obj.vertices.push(vertex);
+        self.generated.push(match normal {
+            Some(_) => None,
+            None => Some(Vector::default()),
+        });
+        self.cache.insert(*key, index);
+        index
+    }
+
+    fn finish(mut self) -> Obj {
+        for (vertex, normal) in self.obj.vertices.iter_mut().zip(&self.generated) {
+            if let Some(normal) = normal {
+                if normal.length() > 0.0 {
+                    vertex.normal = normal.normalize();
+                }
+            }
+        }
+        for (material, triangles) in self.materials.into_iter().zip(self.triangles) {
+            if triangles.is_empty() {
+                continue;
+            }
+            let start = self.obj.indices.len();
+            self.obj.indices.extend(triangles);
+            self.obj.groups.push(Group {
+                material,
+                indices: start..self.obj.indices.len(),
+            });
+        }
+        self.obj
+    }
+}
+
+fn parse_floats(args: &[&str]) -> Result<Vec<f32>> {
+    args.iter()
+        .map(|s| s.parse().map_err(|_| anyhow!("数字格式错误 {}", s)))
+        .collect()
+}
+
+fn to_byte(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// 把从 1 开始的索引或负数相对索引转换成从 0 开始的下标
+fn resolve(s: &str, len: usize) -> Result<usize> {
+    let index: i64 = s.parse().map_err(|_| anyhow!("索引格式错误 {}", s))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= len as i64 {
+        bail!("索引 {} 超出范围, 当前只有 {} 项", index, len);
+    }
+    Ok(resolved as usize)
+}
+
+/// 每个顶点写一组 v / vt / vn, 顶点按第一次被面引用的顺序排列, 没有被引用的顶点不写入.
+/// 这样读回来的顶点顺序不变, 再次写入得到相同的文件. 有非白色的顶点颜色时写成 v x y z r g b
+pub fn write(vertices: &[Vertex], indices: &[(usize, usize, usize)]) -> String {
+    let mut order = vec![];
+    let mut remap = vec![None; vertices.len()];
+    for &(a, b, c) in indices {
+        for i in [a, b, c] {
+            if remap[i].is_none() {
+                remap[i] = Some(order.len() + 1);
+                order.push(i);
+            }
+        }
+    }
+    let white = Color::new(255, 255, 255, 255);
+    let colored = order.iter().any(|&i| {
+        let c = vertices[i].color;
+        Color { a: 255, ..c } != white
+    });
+
+    let mut out = String::new();
+    for &i in &order {
+        let Vertex {
+            position: p, color, ..
+        } = vertices[i];
+        write!(out, "v {} {} {}", p.x, p.y, p.z).unwrap();
+        if colored {
+            let channel = |c: u8| c as f32 / 255.0;
+            let (r, g, b) = (channel(color.r), channel(color.g), channel(color.b));
+            write!(out, " {} {} {}", r, g, b).unwrap();
+        }
+        out.push('\n');
+    }
+    for &i in &order {
+        writeln!(out, "vt {} {}", vertices[i].u, vertices[i].v).unwrap();
+    }
+    for &i in &order {
+        let n = vertices[i].normal;
+        writeln!(out, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+    }
+    for &(a, b, c) in indices {
+        let [a, b, c] = [a, b, c].map(|i| remap[i].unwrap());
+        writeln!(out, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygons_and_relative_indices() {
+        let obj = parse(
+            "o quad\n\
+             v 0 0 0\n\
+             v 1 0 0\n\
+             v 1 1 0\n\
+             v 0 1 0\n\
+             vt 0 0 0\n\
+             vn 0 0 1\n\
+             g front\n\
+             f\t-4/1/1  -3/1/1 -2/1/1 -1/1/1 # 四边形\n\
+             f 1//1 2//1 \\\n   3//1\n",
+        )
+        .unwrap();
+        assert_eq!(obj.indices, vec![(0, 1, 2), (0, 2, 3), (4, 5, 6)]);
+        assert_eq!(obj.vertices.len(), 7);
+        assert_eq!(obj.vertices[4].normal, Vector::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_generated_normals() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n";
+        let smooth = parse(&format!("{}f 1 2 3\nf 1 4 2\n", source)).unwrap();
+        assert_eq!(smooth.vertices.len(), 4);
+        assert_eq!(smooth.vertices[2].normal, Vector::new(0.0, 0.0, 1.0));
+        let n = smooth.vertices[0].normal;
+        assert!((n.y + n.z - 2.0f32.sqrt()).abs() < 1e-6 && n.y > 0.0);
+
+        let flat = parse(&format!("{}s off\nf 1 2 3\nf 1 4 2\n", source)).unwrap();
+        assert_eq!(flat.vertices.len(), 6);
+        assert_eq!(flat.vertices[0].normal, Vector::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_materials() {
+        let obj = parse(
+            "mtllib a.mtl b.mtl
+             v 0 0 0
+v 1 0 0
+v 0 1 0
+             f 1 2 3
+             usemtl skin
+f 1 2 3
+             usemtl hair
+f 1 2 3
+             usemtl skin
+f 3 2 1
+",
+        )
+        .unwrap();
+        assert_eq!(obj.libraries, vec!["a.mtl", "b.mtl"]);
+        assert_eq!(
+            obj.indices,
+            vec![(0, 1, 2), (0, 1, 2), (2, 1, 0), (0, 1, 2)]
+        );
+        let groups: Vec<_> = obj
+            .groups
+            .iter()
+            .map(|g| (g.material.as_deref(), g.indices.clone()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![(None, 0..1), (Some("skin"), 1..3), (Some("hair"), 3..4)]
+        );
+    }
+
+    #[test]
+    fn test_errors_have_line_numbers() {
+        let error = parse("v 0 0 0\nv 1 0 0\nf 1 2 3\n").unwrap_err();
+        assert!(error.to_string().contains("第 3 行"), "{}", error);
+        let error = parse("v 0 0 0\nv 1 0\n").unwrap_err();
+        assert!(error.to_string().contains("第 2 行"), "{}", error);
+        assert!(parse("v 0 0 0\nf 1 1 0\n").is_err());
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let white = Color::new(255, 255, 255, 255);
+        let vertex = |x: f32, u: f32, color: Color| {
+            Vertex::new(
+                Vector::new(x, 0.1, -0.5),
+                Vector::new(0.0, 0.0, 1.0),
+                u,
+                1.0 / 3.0,
+                color,
+            )
+        };
+        let vertices = vec![
+            // 没有被引用的顶点不写入
+            vertex(9.0, 0.0, white),
+            vertex(2.0, 0.25, Color::new(255, 0, 0, 255)),
+            vertex(1.0, 0.5, white),
+            vertex(3.0, 0.75, Color::new(1, 2, 3, 255)),
+        ];
+        let text = write(&vertices, &[(2, 1, 3), (3, 1, 2)]);
+        let obj = parse(&text).unwrap();
+        assert_eq!(obj.vertices, vec![vertices[2], vertices[1], vertices[3]]);
+        assert_eq!(obj.indices, vec![(0, 1, 2), (2, 1, 0)]);
+        assert_eq!(write(&obj.vertices, &obj.indices), text);
+    }
+}
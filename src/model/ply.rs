@@ -0,0 +1,443 @@
+//! Stanford PLY 解析, 支持 ascii / binary_little_endian / binary_big_endian 三种格式.
+//!
+//! 读取 vertex 元素的 x y z, nx ny nz, uv (u v / s t / texture_u texture_v) 和
+//! red green blue alpha, face 元素的 vertex_indices 列表. 多边形按扇形切成三角形,
+//! 没有法线时按共用顶点的面平均生成. 其他元素和属性跳过.
+use crate::{color::Color, vector::Vector, vertex::Vertex};
+use anyhow::{anyhow, bail, Context, Result};
+use std::convert::TryInto;
+
+#[derive(Clone, Debug, Default)]
+pub struct Ply {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<(usize, usize, usize)>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    Binary { big_endian: bool },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Result<Type> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => bail!("不支持的属性类型 {}", name),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(Type),
+    /// 元素个数的类型和元素的类型
+    List(Type, Type),
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+pub fn parse(data: &[u8]) -> Result<Ply> {
+    let (format, elements, header_lines, body) = parse_header(data)?;
+    let mut reader = match format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(body).context("ascii 格式的 ply 不是 UTF-8")?;
+            Reader::Ascii {
+                lines: text.lines(),
+                words: vec![],
+                line: header_lines,
+            }
+        }
+        Format::Binary { big_endian } => Reader::Binary {
+            data: body,
+            offset: 0,
+            big_endian,
+        },
+    };
+
+    let mut ply = Ply::default();
+    let mut has_normals = false;
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                has_normals = element.properties.iter().any(|(name, _)| name == "nx");
+                // 个数来自文件头, 不可信. 每个顶点至少占一个字节
+                ply.vertices.reserve(element.count.min(body.len()));
+                for i in 0..element.count {
+                    let values = reader
+                        .element(element)
+                        .with_context(|| reader.location(element, i))?;
+                    ply.vertices.push(vertex(element, &values));
+                }
+            }
+            "face" => {
+                let list = element
+                    .properties
+                    .iter()
+                    .position(|(name, property)| {
+                        matches!(property, Property::List(..))
+                            && (name == "vertex_indices" || name == "vertex_index")
+                    })
+                    .ok_or_else(|| anyhow!("face 缺少 vertex_indices"))?;
+                for i in 0..element.count {
+                    let values = reader
+                        .element(element)
+                        .with_context(|| reader.location(element, i))?;
+                    let face = values[list]
+                        .iter()
+                        .map(|&index| match index {
+                            _ if index.fract() != 0.0 || index < 0.0 => {
+                                bail!("顶点索引格式错误 {}", index)
+                            }
+                            _ if index as usize >= ply.vertices.len() => bail!(
+                                "顶点索引 {} 超出范围, 只有 {} 个顶点",
+                                index,
+                                ply.vertices.len()
+                            ),
+                            _ => Ok(index as usize),
+                        })
+                        .collect::<Result<Vec<usize>>>()
+                        .with_context(|| reader.location(element, i))?;
+                    for j in 2..face.len() {
+                        ply.indices.push((face[0], face[j - 1], face[j]));
+                    }
+                }
+            }
+            _ => {
+                for i in 0..element.count {
+                    reader
+                        .element(element)
+                        .with_context(|| reader.location(element, i))?;
+                }
+            }
+        }
+    }
+    if !has_normals {
+        generate_normals(&mut ply);
+    }
+    Ok(ply)
+}
+
+/// 返回格式, 元素定义, 文件头的行数和数据部分
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize, &[u8])> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut offset = 0;
+    let mut number = 0;
+    loop {
+        let end = data[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| offset + i)
+            .ok_or_else(|| anyhow!("ply 文件头没有 end_header"))?;
+        let line = std::str::from_utf8(&data[offset..end])
+            .map_err(|_| anyhow!("ply 文件头不是 UTF-8"))?
+            .trim();
+        offset = end + 1;
+        number += 1;
+        if number == 1 {
+            if line != "ply" {
+                bail!("不是 ply 文件");
+            }
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words[..] {
+            ["end_header"] => break,
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::Binary { big_endian: false },
+                    "binary_big_endian" => Format::Binary { big_endian: true },
+                    _ => bail!("ply 第 {} 行: 不支持的格式 {}", number, name),
+                });
+                Ok(())
+            }
+            ["element", name, count] => count
+                .parse()
+                .map(|count| {
+                    elements.push(Element {
+                        name: name.to_string(),
+                        count,
+                        properties: vec![],
+                    })
+                })
+                .map_err(|_| anyhow!("元素个数格式错误 {}", count)),
+            ["property", "list", count, item, name] => add_property(&mut elements, name, || {
+                Ok(Property::List(Type::parse(count)?, Type::parse(item)?))
+            }),
+            ["property", ty, name] => add_property(&mut elements, name, || {
+                Ok(Property::Scalar(Type::parse(ty)?))
+            }),
+            ["comment", ..] | ["obj_info", ..] | [] => Ok(()),
+            _ => Err(anyhow!("无法识别的语句")),
+        };
+        result.with_context(|| format!("ply 第 {} 行格式错误: {}", number, line))?;
+    }
+    let format = format.ok_or_else(|| anyhow!("ply 文件头缺少 format"))?;
+    Ok((format, elements, number, &data[offset..]))
+}
+
+fn add_property(
+    elements: &mut [Element],
+    name: &str,
+    property: impl FnOnce() -> Result<Property>,
+) -> Result<()> {
+    let element = elements
+        .last_mut()
+        .ok_or_else(|| anyhow!("property 出现在 element 之前"))?;
+    element.properties.push((name.to_string(), property()?));
+    Ok(())
+}
+
+/// 数据部分的读取位置
+enum Reader<'a> {
+    /// 每个元素占一行
+    Ascii {
+        lines: std::str::Lines<'a>,
+        /// 当前行剩下的值, 倒序存放
+        words: Vec<&'a str>,
+        line: usize,
+    },
+    Binary {
+        data: &'a [u8],
+        offset: usize,
+        big_endian: bool,
+    },
+}
+
+impl Reader<'_> {
+    /// 读取一个元素, 每个属性的值放在一个数组中, 标量属性只有一个值
+    fn element(&mut self, element: &Element) -> Result<Vec<Vec<f64>>> {
+        if let Reader::Ascii { lines, words, line } = self {
+            let text = loop {
+                *line += 1;
+                match lines.next() {
+                    Some(text) if text.trim().is_empty() => continue,
+                    Some(text) => break text,
+                    None => bail!("数据不完整"),
+                }
+            };
+            *words = text.split_whitespace().rev().collect();
+        }
+        let mut values = Vec::with_capacity(element.properties.len());
+        for (_, property) in &element.properties {
+            values.push(match *property {
+                Property::Scalar(ty) => vec![self.value(ty)?],
+                Property::List(count, item) => {
+                    let count = self.value(count)?;
+                    if count.fract() != 0.0 || count < 0.0 {
+                        bail!("列表长度格式错误 {}", count);
+                    }
+                    (0..count as usize)
+                        .map(|_| self.value(item))
+                        .collect::<Result<_>>()?
+                }
+            });
+        }
+        Ok(values)
+    }
+
+    fn value(&mut self, ty: Type) -> Result<f64> {
+        match self {
+            Reader::Ascii { words, .. } => {
+                let word = words.pop().ok_or_else(|| anyhow!("数据不完整"))?;
+                word.parse().map_err(|_| anyhow!("数字格式错误 {}", word))
+            }
+            Reader::Binary {
+                data,
+                offset,
+                big_endian,
+            } => {
+                let bytes = data
+                    .get(*offset..*offset + ty.size())
+                    .ok_or_else(|| anyhow!("数据不完整"))?;
+                *offset += ty.size();
+                let mut buffer = [0; 8];
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..bytes.len()].reverse();
+                }
+                let b = buffer;
+                Ok(match ty {
+                    Type::I8 => b[0] as i8 as f64,
+                    Type::U8 => b[0] as f64,
+                    Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+                    Type::U32 => u32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+                    Type::F32 => f32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+                    Type::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+
+    /// 错误信息中的位置, ascii 格式用行号
+    fn location(&self, element: &Element, index: usize) -> String {
+        match self {
+            Reader::Ascii { line, .. } => format!("ply 第 {} 行格式错误", line),
+            Reader::Binary { .. } => format!("ply 第 {} 个 {} 格式错误", index + 1, element.name),
+        }
+    }
+}
+
+fn vertex(element: &Element, values: &[Vec<f64>]) -> Vertex {
+    let get = |names: &[&str]| {
+        element
+            .properties
+            .iter()
+            .position(|(name, property)| {
+                matches!(property, Property::Scalar(_)) && names.contains(&name.as_str())
+            })
+            .map(|i| match element.properties[i].1 {
+                Property::Scalar(ty) => (values[i][0], ty),
+                Property::List(..) => unreachable!(),
+            })
+    };
+    let float = |names: &[&str]| get(names).map_or(0.0, |(v, _)| v as f32);
+    // 整数颜色范围 0 到 255, 浮点颜色范围 0 到 1
+    let channel = |names: &[&str]| match get(names) {
+        Some((v, Type::F32 | Type::F64)) => (v.clamp(0.0, 1.0) * 255.0).round() as u8,
+        Some((v, _)) => v.clamp(0.0, 255.0) as u8,
+        None => 255,
+    };
+    let position = Vector::new(float(&["x"]), float(&["y"]), float(&["z"]));
+    let normal = Vector::new(float(&["nx"]), float(&["ny"]), float(&["nz"]));
+    let u = float(&["u", "s", "texture_u", "texture_s"]);
+    let v = float(&["v", "t", "texture_v", "texture_t"]);
+    let color = Color::new(
+        channel(&["red", "r", "diffuse_red"]),
+        channel(&["green", "g", "diffuse_green"]),
+        channel(&["blue", "b", "diffuse_blue"]),
+        channel(&["alpha", "a"]),
+    );
+    Vertex::new(position, normal, u, v, color)
+}
+
+/// 共用顶点的面法线按面积加权平均
+fn generate_normals(ply: &mut Ply) {
+    let mut normals = vec![Vector::new(0.0, 0.0, 0.0); ply.vertices.len()];
+    for &(a, b, c) in &ply.indices {
+        let pa = ply.vertices[a].position;
+        let normal = (&ply.vertices[b].position - &pa).cross(&(&ply.vertices[c].position - &pa));
+        for i in [a, b, c] {
+            normals[i].x += normal.x;
+            normals[i].y += normal.y;
+            normals[i].z += normal.z;
+        }
+    }
+    for (vertex, normal) in ply.vertices.iter_mut().zip(normals) {
+        vertex.normal = normal.normalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4\n\
+                          property float x\nproperty float y\nproperty float z\n\
+                          property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                          element face 1\nproperty list uchar int vertex_indices\n\
+                          property uchar flags\n\
+                          end_header\n";
+
+    fn check(ply: &Ply) {
+        assert_eq!(ply.vertices.len(), 4);
+        assert_eq!(ply.indices, vec![(0, 1, 2), (0, 2, 3)]);
+        assert_eq!(ply.vertices[1].position, Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(ply.vertices[1].color, Color::new(255, 0, 0, 255));
+        assert_eq!(ply.vertices[3].color, Color::new(0, 0, 128, 255));
+        assert_eq!(ply.vertices[2].normal, Vector::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_ascii() {
+        let text = format!(
+            "ply\nformat ascii 1.0\ncomment 扫描数据\n{}\
+             0 0 0 255 255 255\n1 0 0 255 0 0\n\n1 1 0 0 255 0\n0 1 0 0 0 128\n\
+             4 0 1 2 3 7\n",
+            HEADER
+        );
+        check(&parse(text.as_bytes()).unwrap());
+
+        let error = parse(text.replace("1 1 0 0", "1 x 0 0").as_bytes()).unwrap_err();
+        assert!(format!("{:#}", error).contains("第 18 行"), "{:#}", error);
+        let error = parse(text.replace("4 0 1 2 3", "4 0 1 2 9").as_bytes()).unwrap_err();
+        assert!(format!("{:#}", error).contains("超出范围"), "{:#}", error);
+
+        // 文件头声明的个数远多于实际数据
+        let text = text.replace("element vertex 4", "element vertex 4000000000");
+        assert!(parse(text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_binary() {
+        for big_endian in [false, true] {
+            let name = if big_endian { "big" } else { "little" };
+            let mut data =
+                format!("ply\nformat binary_{}_endian 1.0\n{}", name, HEADER).into_bytes();
+            let vertices = [
+                ([0.0f32, 0.0, 0.0], [255u8, 255, 255]),
+                ([1.0, 0.0, 0.0], [255, 0, 0]),
+                ([1.0, 1.0, 0.0], [0, 255, 0]),
+                ([0.0, 1.0, 0.0], [0, 0, 128]),
+            ];
+            for (position, color) in vertices {
+                for v in position {
+                    if big_endian {
+                        data.extend_from_slice(&v.to_be_bytes());
+                    } else {
+                        data.extend_from_slice(&v.to_le_bytes());
+                    }
+                }
+                data.extend_from_slice(&color);
+            }
+            data.push(4);
+            for i in 0..4i32 {
+                if big_endian {
+                    data.extend_from_slice(&i.to_be_bytes());
+                } else {
+                    data.extend_from_slice(&i.to_le_bytes());
+                }
+            }
+            data.push(0);
+            check(&parse(&data).unwrap());
+            assert!(parse(&data[..data.len() - 2]).is_err());
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! STL 解析, 支持 ascii 和二进制格式.
+//!
+//! 每个面片的法线写入它的三个顶点, 位置和法线都相同的顶点共用. 法线为 0 时用顶点计算面法线.
+use crate::{color::Color, vector::Vector, vertex::Vertex};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// 二进制格式: 80 字节文件头, 4 字节面片数, 每个面片 50 字节
+const HEADER_SIZE: usize = 84;
+const FACET_SIZE: usize = 50;
+/// 写入的文件头, 不能以 solid 开头, 否则有些软件会当成 ascii 格式
+const WRITE_HEADER: &[u8] = b"binary stl written by renderer-rs";
+
+#[derive(Clone, Debug, Default)]
+pub struct Stl {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<(usize, usize, usize)>,
+}
+
+#[derive(Default)]
+struct Parser {
+    stl: Stl,
+    /// 位置和法线的位模式, 用来共用顶点
+    cache: HashMap<[u32; 6], usize>,
+}
+
+/// 有些二进制文件的文件头也以 solid 开头, 所以先按文件大小判断是不是二进制格式
+pub fn parse(data: &[u8]) -> Result<Stl> {
+    let binary = data.len() >= HEADER_SIZE && {
+        let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+        data.len() == HEADER_SIZE + count * FACET_SIZE
+    };
+    if binary || !data.trim_ascii_start().starts_with(b"solid") {
+        parse_binary(data)
+    } else {
+        let text = std::str::from_utf8(data).context("ascii 格式的 stl 不是 UTF-8")?;
+        parse_ascii(text)
+    }
+}
+
+fn parse_binary(data: &[u8]) -> Result<Stl> {
+    if data.len() < HEADER_SIZE {
+        bail!("stl 文件不完整");
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    let facets = &data[HEADER_SIZE..];
+    if facets.len() < count * FACET_SIZE {
+        bail!(
+            "stl 文件不完整, 应有 {} 个面片, 只有 {} 字节",
+            count,
+            facets.len()
+        );
+    }
+    let mut parser = Parser::default();
+    for facet in facets.chunks_exact(FACET_SIZE).take(count) {
+        let v: Vec<f32> = facet[..48]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        let vector = |i: usize| Vector::new(v[i * 3], v[i * 3 + 1], v[i * 3 + 2]);
+        // 最后 2 个字节是属性, 各软件的用法不统一, 忽略
+        parser.add_facet(vector(0), &[vector(1), vector(2), vector(3)]);
+    }
+    Ok(parser.stl)
+}
+
+fn parse_ascii(text: &str) -> Result<Stl> {
+    let mut parser = Parser::default();
+    let mut normal = None;
+    let mut positions = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words[..] {
+            ["facet", "normal", ..] => parse_vector(&words[2..]).map(|n| {
+                normal = Some(n);
+                positions.clear();
+            }),
+            ["vertex", ..] => parse_vector(&words[1..]).map(|p| positions.push(p)),
+            ["endfacet"] => match normal.take() {
+                Some(n) if positions.len() >= 3 => {
+                    parser.add_facet(n, &positions);
+                    Ok(())
+                }
+                Some(_) => Err(anyhow!("面片至少需要 3 个顶点")),
+                None => Err(anyhow!("endfacet 之前没有 facet")),
+            },
+            ["solid", ..] | ["endsolid", ..] | ["outer", "loop"] | ["endloop"] | [] => Ok(()),
+            _ => Err(anyhow!("无法识别的语句")),
+        };
+        result.with_context(|| format!("stl 第 {} 行格式错误: {}", index + 1, line.trim()))?;
+    }
+    if normal.is_some() {
+        bail!("stl 最后一个面片没有 endfacet");
+    }
+    Ok(parser.stl)
+}
+
+fn parse_vector(args: &[&str]) -> Result<Vector> {
+    let v = args
+        .iter()
+        .map(|s| s.parse().map_err(|_| anyhow!("数字格式错误 {}", s)))
+        .collect::<Result<Vec<f32>>>()?;
+    match v[..] {
+        [x, y, z] => Ok(Vector::new(x, y, z)),
+        _ => bail!("应有 3 个分量"),
+    }
+}
+
+impl Parser {
+    /// 多边形按扇形切成三角形
+    fn add_facet(&mut self, normal: Vector, positions: &[Vector]) {
+        let normal = if normal.length() > 0.0 {
+            normal.normalize()
+        } else {
+            let (a, b, c) = (positions[0], positions[1], positions[2]);
+            (&b - &a).cross(&(&c - &a)).normalize()
+        };
+        let indices: Vec<usize> = positions.iter().map(|p| self.vertex(*p, normal)).collect();
+        for i in 2..indices.len() {
+            self.stl
+                .indices
+                .push((indices[0], indices[i - 1], indices[i]));
+        }
+    }
+
+    fn vertex(&mut self, position: Vector, normal: Vector) -> usize {
+        let key = [
+            position.x, position.y, position.z, normal.x, normal.y, normal.z,
+        ]
+        // 加 0 把 -0 变成 0, 否则两者的位模式不同
+        .map(|x| (x + 0.0).to_bits());
+        let vertices = &mut self.stl.vertices;
+        *self.cache.entry(key).or_insert_with(|| {
+            let white = Color::new(255, 255, 255, 255);
+            vertices.push(Vertex::new(position, normal, 0.0, 0.0, white));
+            vertices.len() - 1
+        })
+    }
+}
+
+/// 写成二进制格式. 面片法线由顶点位置计算, 和顶点法线无关, 所以读回来再写入得到相同的文件
+pub fn write(vertices: &[Vertex], indices: &[(usize, usize, usize)]) -> Vec<u8> {
+    let mut out = WRITE_HEADER.to_vec();
+    out.resize(80, 0);
+    out.extend_from_slice(&(indices.len() as u32).to_le_bytes());
+    for &(a, b, c) in indices {
+        let [a, b, c] = [a, b, c].map(|i| vertices[i].position);
+        let normal = (&b - &a).cross(&(&c - &a)).normalize();
+        for v in [normal, a, b, c] {
+            for x in [v.x, v.y, v.z] {
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        let stl = parse(
+            b"solid cube\n\
+              facet normal 0 0 -1\n outer loop\n\
+              vertex 0 0 0\n vertex 0 1 0\n vertex 1 1 0\n\
+              endloop\nendfacet\n\
+              facet normal 0 0 0\n outer loop\n\
+              vertex 0 0 0\n vertex 1 1 0\n vertex 1 0 0\n\
+              endloop\nendfacet\n\
+              endsolid cube\n",
+        )
+        .unwrap();
+        assert_eq!(stl.indices, vec![(0, 1, 2), (0, 2, 3)]);
+        assert_eq!(stl.vertices.len(), 4);
+        assert_eq!(stl.vertices[3].normal, Vector::new(0.0, 0.0, -1.0));
+
+        let error = parse(b"solid a\nfacet normal 0 0 1\nvertex 0 0\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("第 3 行"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_binary() {
+        // 文件头以 solid 开头的二进制文件
+        let mut data = b"solid exported".to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&2u32.to_le_bytes());
+        let facets = [
+            [
+                0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            [0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0],
+        ];
+        for facet in facets {
+            for v in facet {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+        let stl = parse(&data).unwrap();
+        assert_eq!(stl.indices, vec![(0, 1, 2), (3, 4, 5)]);
+        assert_eq!(stl.vertices[0].normal, Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(stl.vertices[5].normal, Vector::new(0.0, 1.0, 0.0));
+        assert!(parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let white = Color::new(255, 255, 255, 255);
+        let vertices: Vec<Vertex> = [(0.0, 0.0), (1.5, 0.0), (0.0, 0.7), (1.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| Vertex::new(Vector::new(x, y, 0.3), Vector::default(), 0.0, 0.0, white))
+            .collect();
+        let data = write(&vertices, &[(0, 1, 2), (2, 1, 3)]);
+        assert_eq!(data.len(), HEADER_SIZE + 2 * FACET_SIZE);
+        let stl = parse(&data).unwrap();
+        // 法线相同的面片共用顶点
+        assert_eq!(stl.indices, vec![(0, 1, 2), (2, 1, 3)]);
+        assert_eq!(stl.vertices[3].position, vertices[3].position);
+        assert_eq!(stl.vertices[0].normal, Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(write(&stl.vertices, &stl.indices), data);
+    }
+}
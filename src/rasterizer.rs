@@ -0,0 +1,233 @@
+//! 半空间 (边函数) 三角形光栅化.
+//!
+//! 顶点坐标先对齐到 1/256 像素的定点网格, 边函数用整数计算, 相邻三角形在公共边上的结果严格互为相反数;
+//! 再配合左上填充规则, 公共边上的像素只会被其中一个三角形覆盖, 不会出现缝隙或重复混合.
+use crate::vector::Vector;
+
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+const HALF_PIXEL: i64 = SUBPIXEL / 2;
+/// 超出这个范围的坐标在定点计算时可能溢出, 这样的三角形直接丢弃
+const MAX_COORDINATE: f32 = (1 << 20) as f32;
+
+/// 像素范围 [x0, x1) x [y0, y1)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Rect {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Self {
+        Rect { x0, y0, x1, y1 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fragment {
+    pub x: u32,
+    pub y: u32,
+    /// 观察空间深度, 越小越近
+    pub depth: f32,
+    /// 经过透视校正的重心坐标, 分别对应三个顶点
+    pub weights: [f32; 3],
+}
+
+/// 三角形的光栅化准备数据
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    /// 定点屏幕坐标
+    points: [(i64, i64); 3],
+    /// 各顶点的 1 / w, 用于透视校正
+    inv_w: [f32; 3],
+    /// 两倍有向面积, 为负时三角形是逆时针的, 边函数全部取反
+    area: i64,
+}
+
+impl Triangle {
+    /// 顶点是屏幕坐标, w 分量保存 1 / w. 退化或坐标超出范围时返回 None
+    pub fn new(a: &Vector, b: &Vector, c: &Vector) -> Option<Self> {
+        let mut points = [(0, 0); 3];
+        for (point, v) in points.iter_mut().zip(&[a, b, c]) {
+            if !(v.x.abs() < MAX_COORDINATE && v.y.abs() < MAX_COORDINATE) {
+                return None;
+            }
+            *point = (
+                (v.x * SUBPIXEL as f32).round() as i64,
+                (v.y * SUBPIXEL as f32).round() as i64,
+            );
+        }
+        let area = edge(points[0], points[1], points[2]);
+        if area == 0 {
+            return None;
+        }
+        Some(Triangle {
+            points,
+            inv_w: [a.w, b.w, c.w],
+            area,
+        })
+    }
+
+    /// 屏幕上顺时针 (y 轴向下) 为正
+    pub fn is_clockwise(&self) -> bool {
+        self.area > 0
+    }
+
+    /// 覆盖到的像素范围, 与 clip 求交, 没有交集时返回 None
+    pub fn bounds(&self, clip: Rect) -> Option<Rect> {
+        let xs = self.points.iter().map(|p| p.0);
+        let ys = self.points.iter().map(|p| p.1);
+        // 像素中心在 (x + 0.5, y + 0.5)
+        let first = |v: i64| (v - HALF_PIXEL + SUBPIXEL - 1).div_euclid(SUBPIXEL);
+        let last = |v: i64| (v - HALF_PIXEL).div_euclid(SUBPIXEL) + 1;
+        let x0 = first(xs.clone().min().unwrap()).max(clip.x0 as i64);
+        let x1 = last(xs.max().unwrap()).min(clip.x1 as i64);
+        let y0 = first(ys.clone().min().unwrap()).max(clip.y0 as i64);
+        let y1 = last(ys.max().unwrap()).min(clip.y1 as i64);
+        if x0 >= x1 || y0 >= y1 {
+            return None;
+        }
+        Some(Rect::new(x0 as u32, y0 as u32, x1 as u32, y1 as u32))
+    }
+
+    /// 对 clip 范围内被三角形覆盖的每个像素调用 f
+    pub fn rasterize<F>(&self, clip: Rect, mut f: F)
+    where
+        F: FnMut(Fragment),
+    {
+        let bounds = match self.bounds(clip) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let sign = self.area.signum();
+        let [a, b, c] = self.points;
+        // 第 i 条边是第 i 个顶点的对边, 它的边函数就是该顶点的重心坐标分量
+        let edges = [(b, c), (c, a), (a, b)];
+
+        let origin = (
+            bounds.x0 as i64 * SUBPIXEL + HALF_PIXEL,
+            bounds.y0 as i64 * SUBPIXEL + HALF_PIXEL,
+        );
+        let mut row = [0; 3];
+        let mut step_x = [0; 3];
+        let mut step_y = [0; 3];
+        let mut bias = [0; 3];
+        for (i, &(p, q)) in edges.iter().enumerate() {
+            row[i] = edge(p, q, origin) * sign;
+            step_x[i] = -(q.1 - p.1) * SUBPIXEL * sign;
+            step_y[i] = (q.0 - p.0) * SUBPIXEL * sign;
+            // 不是左上边时, 正好落在边上的像素不算在内
+            bias[i] = if is_top_left(p, q, sign) { 0 } else { -1 };
+        }
+
+        let area = (self.area * sign) as f32;
+        for y in bounds.y0..bounds.y1 {
+            let mut w = row;
+            for x in bounds.x0..bounds.x1 {
+                if (0..3).all(|i| w[i] + bias[i] >= 0) {
+                    let mut weights = [0.0; 3];
+                    let mut sum = 0.0;
+                    for i in 0..3 {
+                        weights[i] = w[i] as f32 / area * self.inv_w[i];
+                        sum += weights[i];
+                    }
+                    for weight in weights.iter_mut() {
+                        *weight /= sum;
+                    }
+                    f(Fragment {
+                        x,
+                        y,
+                        depth: 1.0 / sum,
+                        weights,
+                    });
+                }
+                for i in 0..3 {
+                    w[i] += step_x[i];
+                }
+            }
+            for i in 0..3 {
+                row[i] += step_y[i];
+            }
+        }
+    }
+}
+
+/// 点 r 相对有向边 p -> q 的边函数, 等于三角形 pqr 的两倍有向面积
+fn edge(p: (i64, i64), q: (i64, i64), r: (i64, i64)) -> i64 {
+    (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)
+}
+
+/// 按顺时针方向看, 上边是向右的水平边, 左边是向上的边
+fn is_top_left(p: (i64, i64), q: (i64, i64), sign: i64) -> bool {
+    let dx = (q.0 - p.0) * sign;
+    let dy = (q.1 - p.1) * sign;
+    dy < 0 || (dy == 0 && dx > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coverage(triangles: &[[Vector; 3]], size: u32) -> Vec<u32> {
+        let mut counts = vec![0; (size * size) as usize];
+        let clip = Rect::new(0, 0, size, size);
+        for [a, b, c] in triangles {
+            if let Some(t) = Triangle::new(a, b, c) {
+                t.rasterize(clip, |f| counts[(f.y * size + f.x) as usize] += 1);
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_shared_edges_are_watertight() {
+        // 一个正方形切成共用中心点的扇形, 顶点都在像素中心和像素边界上, 最容易出错
+        let center = Vector::new(8.0, 8.5, 0.0);
+        let corners = [
+            Vector::new(1.0, 1.0, 0.0),
+            Vector::new(8.5, 0.5, 0.0),
+            Vector::new(15.0, 1.0, 0.0),
+            Vector::new(15.5, 8.5, 0.0),
+            Vector::new(15.0, 15.0, 0.0),
+            Vector::new(8.5, 15.5, 0.0),
+            Vector::new(1.0, 15.0, 0.0),
+            Vector::new(0.5, 8.0, 0.0),
+        ];
+        let mut triangles = vec![];
+        for i in 0..corners.len() {
+            let next = corners[(i + 1) % corners.len()];
+            // 混合两种环绕方向
+            if i % 2 == 0 {
+                triangles.push([center, corners[i], next]);
+            } else {
+                triangles.push([next, corners[i], center]);
+            }
+        }
+        let counts = coverage(&triangles, 16);
+        assert!(counts.iter().all(|&c| c <= 1));
+
+        // 扇形覆盖的像素和整个八边形一次性光栅化的结果一致
+        let mut fan = vec![];
+        for i in 1..corners.len() - 1 {
+            fan.push([corners[0], corners[i], corners[i + 1]]);
+        }
+        assert_eq!(counts, coverage(&fan, 16));
+    }
+
+    #[test]
+    fn test_weights() {
+        let a = Vector::new(0.0, 0.0, 0.0);
+        let b = Vector::new(4.0, 0.0, 0.0);
+        let c = Vector::new(0.0, 4.0, 0.0);
+        let t = Triangle::new(&a, &b, &c).unwrap();
+        let mut fragments = vec![];
+        t.rasterize(Rect::new(0, 0, 4, 4), |f| fragments.push(f));
+        // 斜边上的像素中心 (1.5, 2.5) 等不在左上边上, 不覆盖
+        assert_eq!(fragments.len(), 6);
+        let f = fragments[0];
+        assert_eq!((f.x, f.y), (0, 0));
+        assert_eq!(f.weights, [0.75, 0.125, 0.125]);
+    }
+}
@@ -0,0 +1,168 @@
+//! 逐帧渲染动画, 每一帧的状态只由帧号决定, 不依赖实际时间和窗口事件
+use crate::{
+    camera::Camera, canvas::Canvas, framebuffer::FrameBuffer, image::gif::GifEncoder,
+    interpolate::Interpolate, matrix::Matrix, mesh::Mesh, vector::Vector,
+};
+use anyhow::{bail, Result};
+use std::f32::consts::PI;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Animation {
+    /// 模型绕 Y 轴转一整圈
+    Turntable,
+    /// 相机绕目标点转一整圈
+    Orbit,
+    /// 相机依次经过这些点, 首尾两帧分别在起点和终点
+    CameraPath(Vec<Vector>),
+}
+
+pub struct Sequence {
+    pub frames: usize,
+    pub animation: Animation,
+}
+
+impl Sequence {
+    pub fn new(frames: usize, animation: Animation) -> Self {
+        Sequence { frames, animation }
+    }
+
+    /// 计算第 index 帧的模型旋转和相机
+    pub fn frame(&self, index: usize, rotation: Vector, camera: &Camera) -> (Vector, Camera) {
+        let mut rotation = rotation;
+        let mut camera = *camera;
+        // 转圈时不包含终点, 首尾相接播放不会重复
+        let turn = 2.0 * PI * index as f32 / self.frames.max(1) as f32;
+        match &self.animation {
+            Animation::Turntable => rotation.y += turn,
+            Animation::Orbit => {
+                let offset = &camera.position - &camera.target;
+                let offset = Matrix::rotation_y(turn).transform_vector(&offset);
+                let Vector { x, y, z, .. } = camera.target;
+                camera.position = Vector::new(x + offset.x, y + offset.y, z + offset.z);
+            }
+            Animation::CameraPath(points) => {
+                if let Some(p) = path_point(points, index, self.frames) {
+                    camera.position = p;
+                }
+            }
+        }
+        (rotation, camera)
+    }
+
+    /// 依次渲染每一帧并交给 f 处理, 结束后恢复模型和相机
+    pub fn render<F>(&self, canvas: &mut Canvas, mesh: &mut Mesh, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, &FrameBuffer) -> Result<()>,
+    {
+        if self.frames == 0 {
+            bail!("帧数不能为 0");
+        }
+        let rotation = mesh.rotation;
+        let camera = *canvas.camera();
+        let mut result = Ok(());
+        for index in 0..self.frames {
+            let (r, c) = self.frame(index, rotation, &camera);
+            mesh.rotation = r;
+            canvas.set_camera(c);
+            canvas.clear();
+            if canvas.casts_shadows() {
+                canvas.render_shadows(&[mesh]);
+            }
+            canvas.draw_mesh(mesh);
+            result = f(index, canvas.framebuffer());
+            if result.is_err() {
+                break;
+            }
+        }
+        mesh.rotation = rotation;
+        canvas.set_camera(camera);
+        result
+    }
+
+    /// 每一帧写入一张带编号的图片, 格式由扩展名决定
+    pub fn save_images(&self, canvas: &mut Canvas, mesh: &mut Mesh, pattern: &str) -> Result<()> {
+        self.render(canvas, mesh, |index, framebuffer| {
+            framebuffer.save(&frame_path(pattern, index))
+        })
+    }
+
+    /// 所有帧写入一张 gif 动画. delay 为每帧时长, 单位 1/100 秒;
+    /// repeat 为循环次数, 0 表示无限循环
+    pub fn save_gif(
+        &self,
+        canvas: &mut Canvas,
+        mesh: &mut Mesh,
+        path: &str,
+        delay: u16,
+        repeat: Option<u16>,
+    ) -> Result<()> {
+        let mut encoder = GifEncoder::new(canvas.width(), canvas.height(), repeat)?;
+        self.render(canvas, mesh, |_, framebuffer| {
+            encoder.add_frame(&framebuffer.to_rgba_bytes(), delay)
+        })?;
+        encoder.save(path)
+    }
+}
+
+fn path_point(points: &[Vector], index: usize, frames: usize) -> Option<Vector> {
+    match points {
+        [] => None,
+        [p] => Some(*p),
+        _ => {
+            let t = if frames > 1 {
+                index as f32 / (frames - 1) as f32
+            } else {
+                0.0
+            };
+            let segment = t * (points.len() - 1) as f32;
+            let i = (segment as usize).min(points.len() - 2);
+            Some(points[i].interpolate(&points[i + 1], segment - i as f32))
+        }
+    }
+}
+
+/// 生成第 index 帧的文件名. pattern 中的 `{}` 会替换成 4 位帧号,
+/// 没有 `{}` 时把帧号加在扩展名前面, 例如 `out.png` -> `out_0001.png`
+pub fn frame_path(pattern: &str, index: usize) -> String {
+    let number = format!("{:04}", index);
+    if pattern.contains("{}") {
+        return pattern.replacen("{}", &number, 1);
+    }
+    let path = Path::new(pattern);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!(
+                "{}_{}.{}",
+                stem.to_string_lossy(),
+                number,
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}_{}", pattern, number),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("out/frame_{}.png", 7), "out/frame_0007.png");
+        assert_eq!(frame_path("out/turn.png", 12), "out/turn_0012.png");
+        assert_eq!(frame_path("turn", 3), "turn_0003");
+    }
+
+    #[test]
+    fn test_camera_path() {
+        let points = vec![Vector::new(0.0, 0.0, -10.0), Vector::new(0.0, 0.0, -20.0)];
+        let sequence = Sequence::new(3, Animation::CameraPath(points));
+        let camera = Camera::default();
+        let z: Vec<f32> = (0..3)
+            .map(|i| sequence.frame(i, Vector::default(), &camera).1.position.z)
+            .collect();
+        assert_eq!(z, vec![-10.0, -15.0, -20.0]);
+    }
+}
@@ -0,0 +1,553 @@
+//! 可编程着色器.
+//!
+//! 顶点着色器把模型顶点变换到裁剪空间, 同时输出任意类型的 varying; 裁剪和光栅化之后,
+//! varying 按透视校正的重心坐标插值, 交给片元着色器计算颜色.
+//! 用 [`Canvas::draw_mesh_with`](crate::Canvas::draw_mesh_with) 使用自定义的着色器绘制网格.
+use crate::{
+    clipper::ClipVertex,
+    color::Color,
+    interpolate::{Barycentric, Interpolate},
+    light::Light,
+    material::Material,
+    matrix::Matrix,
+    shadow::ShadowMap,
+    texture::Texture,
+    vector::Vector,
+    vertex::Vertex,
+};
+
+/// 顶点着色器输出, 插值之后交给片元着色器的数据.
+/// 裁剪时在裁剪空间中线性插值 (Interpolate), 光栅化时按重心坐标插值 (Barycentric)
+pub trait Varying: Interpolate + Barycentric + Copy + Send + Sync + 'static {}
+
+impl<T: Interpolate + Barycentric + Copy + Send + Sync + 'static> Varying for T {}
+
+/// 一次绘制中所有顶点和片元共用的数据
+#[derive(Copy, Clone, Debug)]
+pub struct Uniforms<'a> {
+    /// 模型空间到世界空间
+    pub world: Matrix,
+    pub view: Matrix,
+    pub projection: Matrix,
+    /// world * view * projection, 模型空间到裁剪空间
+    pub transform: Matrix,
+    /// 世界空间中的光源, 着色时把它们的贡献相加
+    pub lights: &'a [Light],
+    /// 与 lights 一一对应的阴影贴图, 可以比 lights 短
+    pub shadows: &'a [Option<ShadowMap>],
+    /// 相机在世界空间的位置, 由视图矩阵求出
+    pub camera: Vector,
+}
+
+impl Uniforms<'_> {
+    /// 第 i 个光源照到世界空间中 point 的比例, 没有阴影贴图时为 1.
+    /// cos 是法线和光线夹角的余弦
+    pub fn visibility(&self, i: usize, point: &Vector, cos: f32) -> f32 {
+        match self.shadows.get(i) {
+            Some(Some(shadow)) => shadow.visibility(point, cos),
+            _ => 1.0,
+        }
+    }
+}
+
+/// 当前三角形的材质和贴图
+#[derive(Copy, Clone)]
+pub struct Surface<'a> {
+    pub material: Option<&'a Material>,
+    /// 材质的漫反射贴图, 没有时使用 Mesh::texture
+    pub texture: Option<&'a Texture>,
+}
+
+impl Surface<'_> {
+    /// 贴图颜色, 没有贴图时用顶点颜色, 再乘上材质的漫反射颜色和不透明度
+    pub fn albedo(&self, u: f32, v: f32, color: Color) -> Color {
+        let color = match self.texture {
+            Some(texture) => texture.sample(u, v),
+            None => color,
+        };
+        match self.material {
+            Some(material) => material.modulate(color),
+            None => color,
+        }
+    }
+
+    /// 材质的环境光反射率, 没有材质时为 1
+    pub fn ambient(&self) -> Vector {
+        match self.material {
+            Some(material) => material.ambient,
+            None => Vector::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// 高光反射率和高光指数. 材质没有指定高光指数时使用 k 和 shininess,
+    /// 材质有高光贴图时再乘上贴图颜色
+    pub fn specular(&self, u: f32, v: f32, k: f32, shininess: f32) -> (Vector, f32) {
+        let (specular, shininess) = match self.material {
+            Some(m) if m.shininess > 0.0 => (m.specular, m.shininess),
+            _ => (Vector::new(k, k, k), shininess),
+        };
+        match self.material.and_then(|m| m.specular_map.as_ref()) {
+            Some(map) => {
+                let c = map.sample(u, v);
+                let scale = |k: f32, c: u8| k * c as f32 / 255.0;
+                let specular = Vector::new(
+                    scale(specular.x, c.r),
+                    scale(specular.y, c.g),
+                    scale(specular.z, c.b),
+                );
+                (specular, shininess)
+            }
+            None => (specular, shininess),
+        }
+    }
+
+    /// 用材质的切线空间法线贴图扰动插值后的法线, 返回单位向量.
+    /// tangent.w 是副切线方向, 没有法线贴图或没有切线时直接返回 normal
+    pub fn normal(&self, u: f32, v: f32, normal: &Vector, tangent: &Vector) -> Vector {
+        let n = normal.normalize();
+        let map = match self.material.and_then(|m| m.normal_map.as_ref()) {
+            Some(map) if tangent.w != 0.0 => map,
+            _ => return n,
+        };
+        // 插值之后切线和法线不再垂直, 先正交化
+        let t = tangent - &(&n * tangent.dot(&n));
+        if t.length() == 0.0 {
+            return n;
+        }
+        let t = t.normalize();
+        let b = &n.cross(&t) * tangent.w.signum();
+        // 贴图颜色 0 到 255 对应 -1 到 1
+        let c = map.sample(u, v);
+        let decode = |c: u8| c as f32 / 255.0 * 2.0 - 1.0;
+        let (x, y, z) = (decode(c.r), decode(c.g), decode(c.b));
+        let mapped = &(&(&t * x) + &(&b * y)) + &(&n * z);
+        if mapped.length() == 0.0 {
+            return n;
+        }
+        mapped.normalize()
+    }
+}
+
+pub trait VertexShader: Sync {
+    type Varying: Varying;
+
+    /// 返回顶点的裁剪空间坐标和要插值的数据
+    fn vertex(&self, uniforms: &Uniforms, vertex: &Vertex) -> (Vector, Self::Varying);
+}
+
+pub trait FragmentShader<V>: Sync {
+    /// varying 是三个顶点输出插值之后的结果
+    fn fragment(&self, uniforms: &Uniforms, surface: &Surface, varying: &V) -> Color;
+}
+
+/// 默认着色器: 逐顶点计算漫反射光照, 片元颜色是 albedo 乘上插值的光照
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GouraudShader;
+
+/// GouraudShader 的 varying, light 是顶点处所有光源漫反射的 RGB 之和
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GouraudVarying {
+    pub u: f32,
+    pub v: f32,
+    pub color: Color,
+    pub light: Vector,
+}
+
+impl Interpolate for GouraudVarying {
+    fn interpolate(&self, other: &Self, factor: f32) -> Self {
+        GouraudVarying {
+            u: self.u.interpolate(&other.u, factor),
+            v: self.v.interpolate(&other.v, factor),
+            color: self.color.interpolate(&other.color, factor),
+            light: self.light.interpolate(&other.light, factor),
+        }
+    }
+}
+
+impl Barycentric for GouraudVarying {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        GouraudVarying {
+            u: f32::barycentric(&a.u, &b.u, &c.u, weights),
+            v: f32::barycentric(&a.v, &b.v, &c.v, weights),
+            color: Color::barycentric(&a.color, &b.color, &c.color, weights),
+            light: Vector::barycentric(&a.light, &b.light, &c.light, weights),
+        }
+    }
+}
+
+impl VertexShader for GouraudShader {
+    type Varying = GouraudVarying;
+
+    fn vertex(&self, uniforms: &Uniforms, vertex: &Vertex) -> (Vector, GouraudVarying) {
+        let position = uniforms.transform.transform_homogeneous(&vertex.position);
+        let varying = GouraudVarying {
+            u: vertex.u,
+            v: vertex.v,
+            color: vertex.color,
+            light: gouraud_lighting(uniforms, vertex),
+        };
+        (position, varying)
+    }
+}
+
+impl FragmentShader<GouraudVarying> for GouraudShader {
+    fn fragment(&self, _: &Uniforms, surface: &Surface, v: &GouraudVarying) -> Color {
+        // 拆成亮度和光的颜色, 白光时和只有亮度的光照结果相同
+        let Vector { x, y, z, .. } = v.light;
+        let brightness = x.max(y).max(z);
+        let albedo = surface.albedo(v.u, v.v, v.color);
+        if brightness > 0.0 {
+            let tint = Vector::new(x / brightness, y / brightness, z / brightness);
+            albedo.tint(&tint).shading(brightness)
+        } else {
+            albedo.shading(0.0)
+        }
+    }
+}
+
+/// 所有光源在顶点处的漫反射之和: 光的颜色乘上强度和法线与光线夹角的余弦, 余弦小于 0 时为 0.
+/// 阴影也按顶点计算
+pub fn gouraud_lighting(uniforms: &Uniforms, v: &Vertex) -> Vector {
+    let n = uniforms.world.transform_normal(&v.normal);
+    let p = uniforms.world.transform_vector(&v.position);
+    let mut sum = Vector::new(0.0, 0.0, 0.0);
+    for (i, light) in uniforms.lights.iter().enumerate() {
+        let (l, strength) = light.incident(&p);
+        let cos = n.dot(&l).max(0.0);
+        if strength <= 0.0 || cos <= 0.0 {
+            continue;
+        }
+        let strength = strength * uniforms.visibility(i, &p, cos);
+        sum = &sum + &light.radiance(strength * cos);
+    }
+    sum
+}
+
+/// 逐片元的 Blinn-Phong 光照: 环境光, 漫反射和高光.
+/// 环境光乘上材质的 Ka. 材质指定了高光指数时使用材质的高光颜色和指数, 否则使用这里的参数,
+/// 高光再乘上材质的高光贴图
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlinnPhongShader {
+    /// 环境光强度
+    pub ambient: f32,
+    /// 高光强度
+    pub specular: f32,
+    /// 高光指数, 越大高光越小越亮
+    pub shininess: f32,
+}
+
+impl Default for BlinnPhongShader {
+    fn default() -> Self {
+        BlinnPhongShader {
+            ambient: 0.1,
+            specular: 0.5,
+            shininess: 32.0,
+        }
+    }
+}
+
+/// BlinnPhongShader 的 varying, 位置, 法线和切线都在世界空间
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PhongVarying {
+    pub position: Vector,
+    pub normal: Vector,
+    /// w 是副切线方向, 用于法线贴图
+    pub tangent: Vector,
+    pub u: f32,
+    pub v: f32,
+    pub color: Color,
+}
+
+impl Interpolate for PhongVarying {
+    fn interpolate(&self, other: &Self, factor: f32) -> Self {
+        PhongVarying {
+            position: self.position.interpolate(&other.position, factor),
+            normal: self.normal.interpolate(&other.normal, factor),
+            tangent: self.tangent.interpolate(&other.tangent, factor),
+            u: self.u.interpolate(&other.u, factor),
+            v: self.v.interpolate(&other.v, factor),
+            color: self.color.interpolate(&other.color, factor),
+        }
+    }
+}
+
+impl Barycentric for PhongVarying {
+    fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+        PhongVarying {
+            position: Vector::barycentric(&a.position, &b.position, &c.position, weights),
+            normal: Vector::barycentric(&a.normal, &b.normal, &c.normal, weights),
+            tangent: Vector::barycentric(&a.tangent, &b.tangent, &c.tangent, weights),
+            u: f32::barycentric(&a.u, &b.u, &c.u, weights),
+            v: f32::barycentric(&a.v, &b.v, &c.v, weights),
+            color: Color::barycentric(&a.color, &b.color, &c.color, weights),
+        }
+    }
+}
+
+impl VertexShader for BlinnPhongShader {
+    type Varying = PhongVarying;
+
+    fn vertex(&self, uniforms: &Uniforms, vertex: &Vertex) -> (Vector, PhongVarying) {
+        let position = uniforms.transform.transform_homogeneous(&vertex.position);
+        let varying = PhongVarying {
+            position: uniforms.world.transform_homogeneous(&vertex.position),
+            normal: uniforms.world.transform_normal(&vertex.normal),
+            tangent: uniforms.world.transform_direction(&vertex.tangent),
+            u: vertex.u,
+            v: vertex.v,
+            color: vertex.color,
+        };
+        (position, varying)
+    }
+}
+
+impl FragmentShader<PhongVarying> for BlinnPhongShader {
+    fn fragment(&self, uniforms: &Uniforms, surface: &Surface, v: &PhongVarying) -> Color {
+        let albedo = surface.albedo(v.u, v.v, v.color);
+        let n = surface.normal(v.u, v.v, &v.normal, &v.tangent);
+        let e = (&uniforms.camera - &v.position).normalize();
+        let ambient = surface.ambient();
+        let (specular, shininess) = surface.specular(v.u, v.v, self.specular, self.shininess);
+        let mut diffuse = Vector::new(0.0, 0.0, 0.0);
+        let mut highlight = Vector::new(0.0, 0.0, 0.0);
+        for (i, light) in uniforms.lights.iter().enumerate() {
+            let (l, strength) = light.incident(&v.position);
+            let cos = n.dot(&l);
+            // 背光的一面没有高光. 半程向量和法线的夹角代替反射光线和视线的夹角
+            if strength <= 0.0 || cos <= 0.0 {
+                continue;
+            }
+            let strength = strength * uniforms.visibility(i, &v.position, cos);
+            let h = (&l + &e).normalize();
+            diffuse = &diffuse + &light.radiance(strength * cos);
+            highlight = &highlight + &light.radiance(strength * n.dot(&h).max(0.0).powf(shininess));
+        }
+        let channel = |c: u8, ka: f32, diffuse: f32, k: f32, highlight: f32| {
+            let c = c as f32 * (self.ambient * ka + diffuse) + 255.0 * k * highlight;
+            c.round().clamp(0.0, 255.0) as u8
+        };
+        Color::new(
+            channel(albedo.r, ambient.x, diffuse.x, specular.x, highlight.x),
+            channel(albedo.g, ambient.y, diffuse.y, specular.y, highlight.y),
+            channel(albedo.b, ambient.z, diffuse.z, specular.z, highlight.z),
+            albedo.a,
+        )
+    }
+}
+
+/// 顶点着色器的输出: 裁剪空间坐标和 varying
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Shaded<V> {
+    pub position: Vector,
+    pub varying: V,
+}
+
+impl<V: Interpolate> Interpolate for Shaded<V> {
+    fn interpolate(&self, other: &Self, factor: f32) -> Self {
+        Shaded {
+            position: self.position.interpolate(&other.position, factor),
+            varying: self.varying.interpolate(&other.varying, factor),
+        }
+    }
+}
+
+impl<V: Interpolate + Copy> ClipVertex for Shaded<V> {
+    fn clip_position(&self) -> &Vector {
+        &self.position
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{image::RgbaImage, mesh::Mesh, model::gltf, Canvas};
+
+    /// 以 center 为中心, 边长 2 * half 的正方形的 4 个顶点, 依次是 (-, -), (+, -), (+, +), (-, +).
+    /// 法线沿 y 轴时正方形是水平的, 否则在 xy 平面上
+    pub(crate) fn quad(
+        center: &Vector,
+        half: f32,
+        normal: Vector,
+        uv: f32,
+        color: Color,
+    ) -> Vec<Vertex> {
+        [(-half, -half), (half, -half), (half, half), (-half, half)]
+            .iter()
+            .map(|&(a, b)| {
+                let position = if normal.y != 0.0 {
+                    Vector::new(center.x + a, center.y, center.z + b)
+                } else {
+                    Vector::new(center.x + a, center.y + b, center.z)
+                };
+                Vertex::new(position, normal, uv, uv, color)
+            })
+            .collect()
+    }
+
+    /// 自定义的 varying, 只有一个灰度值
+    #[derive(Copy, Clone, Debug)]
+    struct Gray(f32);
+
+    impl Interpolate for Gray {
+        fn interpolate(&self, other: &Self, factor: f32) -> Self {
+            Gray(self.0.interpolate(&other.0, factor))
+        }
+    }
+
+    impl Barycentric for Gray {
+        fn barycentric(a: &Self, b: &Self, c: &Self, weights: [f32; 3]) -> Self {
+            Gray(f32::barycentric(&a.0, &b.0, &c.0, weights))
+        }
+    }
+
+    /// 从左到右由黑变白
+    struct Gradient;
+
+    impl VertexShader for Gradient {
+        type Varying = Gray;
+
+        fn vertex(&self, uniforms: &Uniforms, vertex: &Vertex) -> (Vector, Gray) {
+            let position = uniforms.transform.transform_homogeneous(&vertex.position);
+            (position, Gray((vertex.position.x + 5.0) / 10.0))
+        }
+    }
+
+    impl FragmentShader<Gray> for Gradient {
+        fn fragment(&self, _: &Uniforms, _: &Surface, varying: &Gray) -> Color {
+            let g = (varying.0 * 255.0).round() as u8;
+            Color::new(g, g, g, 255)
+        }
+    }
+
+    #[test]
+    fn test_custom_shader() {
+        let white = Color::new(255, 255, 255, 255);
+        let vertices = quad(&Vector::default(), 5.0, Vector::default(), 0.0, white);
+        let mesh = Mesh::new(vertices, vec![(0, 1, 2), (0, 2, 3)], None);
+        let mut canvas = Canvas::new(64, 64);
+        canvas.clear();
+        canvas.draw_mesh_with(&mesh, &Gradient, &Gradient);
+
+        let framebuffer = canvas.framebuffer();
+        let row: Vec<u8> = (0..64).map(|x| framebuffer.get_pixel(x, 32).r).collect();
+        let covered: Vec<u8> = row.into_iter().filter(|&g| g > 0).collect();
+        assert!(covered.len() > 8);
+        assert!(covered.windows(2).all(|w| w[0] <= w[1]));
+        assert!(covered[0] < 32 && covered[covered.len() - 1] > 223);
+    }
+
+    #[test]
+    fn test_blinn_phong_highlight() {
+        // 相机在 z = -20, 光源在相机和平面之间, 平面正对两者并铺满画面
+        let gray = Color::new(128, 128, 128, 255);
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let vertices = quad(&Vector::default(), 5.0, normal, 0.0, gray);
+        let mesh = Mesh::new(vertices, vec![(0, 1, 2), (0, 2, 3)], None);
+        let mut canvas = Canvas::new(64, 64);
+        assert_eq!(
+            canvas.camera().view_matrix().eye_position(),
+            canvas.camera().position
+        );
+        canvas.clear();
+        let shader = BlinnPhongShader::default();
+        canvas.draw_mesh_with(&mesh, &shader, &shader);
+
+        // 只有漫反射时最亮是 128 * 1.1, 超过说明有高光. 高光偏向光源所在的右上方
+        let framebuffer = canvas.framebuffer();
+        let highlight = framebuffer.get_pixel(40, 24).r;
+        let corner = framebuffer.get_pixel(0, 63).r;
+        assert!(highlight > 250, "{}", highlight);
+        assert!(corner < 150, "{}", corner);
+    }
+
+    #[test]
+    fn test_material_terms() {
+        // 和 test_blinn_phong_highlight 相同的场景, 平面使用材质
+        let gray = Color::new(128, 128, 128, 255);
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let vertices = quad(&Vector::default(), 5.0, normal, 0.5, gray);
+        let render = |material: Material, lights: Vec<Light>| {
+            let mut mesh = Mesh::new(vertices.clone(), vec![(0, 1, 2), (0, 2, 3)], None);
+            mesh.materials = vec![material];
+            mesh.submeshes[0].material = Some(0);
+            let mut canvas = Canvas::new(64, 64);
+            canvas.set_lights(lights);
+            canvas.clear();
+            let shader = BlinnPhongShader::default();
+            canvas.draw_mesh_with(&mesh, &shader, &shader);
+            canvas.framebuffer().get_pixel(40, 24)
+        };
+        let material = || {
+            Material::from_gltf(&gltf::Material {
+                name: String::new(),
+                base_color: [1.0, 1.0, 1.0, 1.0],
+                texture: None,
+                normal_texture: None,
+            })
+        };
+
+        // 没有光源时只剩环境光, 按 Ka 缩放
+        assert_eq!(render(material(), vec![]).r, 13);
+        let red = Material {
+            ambient: Vector::new(1.0, 0.0, 0.0),
+            ..material()
+        };
+        let c = render(red, vec![]);
+        assert_eq!((c.r, c.g), (13, 0));
+
+        // 黑色的高光贴图去掉高光, 只剩环境光和漫反射
+        let lights = Canvas::new(1, 1).lights().to_vec();
+        assert!(render(material(), lights.clone()).r > 250);
+        let black = RgbaImage {
+            width: 1,
+            height: 1,
+            pixels: vec![0, 0, 0, 255],
+        };
+        let matte = Material {
+            specular_map: Some(Texture::from_image(&black)),
+            ..material()
+        };
+        let c = render(matte, lights).r;
+        assert!(c <= 141, "{}", c);
+    }
+
+    #[test]
+    fn test_normal_map() {
+        // 1x1 的法线贴图, 只有一种颜色
+        let material = |rgb: [u8; 3]| {
+            Material::from_gltf(&gltf::Material {
+                name: String::new(),
+                base_color: [1.0, 1.0, 1.0, 1.0],
+                texture: None,
+                normal_texture: Some(RgbaImage {
+                    width: 1,
+                    height: 1,
+                    pixels: vec![rgb[0], rgb[1], rgb[2], 255],
+                }),
+            })
+        };
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let tangent = Vector {
+            w: 1.0,
+            ..Vector::new(1.0, 0.0, 0.0)
+        };
+        let at = |material: &Material, tangent: &Vector| {
+            let surface = Surface {
+                material: Some(material),
+                texture: None,
+            };
+            surface.normal(0.5, 0.5, &normal, tangent)
+        };
+
+        // 朝正上方的法线贴图不改变法线
+        let flat = material([128, 128, 255]);
+        let n = at(&flat, &tangent);
+        assert!((&n - &normal).length() < 0.02, "{:?}", n);
+        // 没有切线时忽略法线贴图
+        let tilted = material([255, 128, 128]);
+        assert_eq!(at(&tilted, &Vector::default()), normal);
+        // 红色分量把法线转向切线方向
+        let n = at(&tilted, &tangent);
+        assert!((n.x - 1.0).abs() < 0.02 && n.z.abs() < 0.02, "{:?}", n);
+    }
+}
@@ -0,0 +1,272 @@
+//! 阴影贴图.
+//!
+//! 从光源的位置用 look_at_lh 和 perspective_fov_lh 把模型再渲染一遍, 只保留深度缓冲.
+//! 着色时把片元变换到光源的屏幕空间, 和阴影贴图中记录的最近深度比较, 更远的就在阴影里.
+//! 比较时减去深度偏移避免表面自己遮挡自己, 并对周围的多个像素做 PCF 平均, 让阴影边缘变得柔和.
+use crate::{
+    camera::Camera,
+    canvas::{self, Canvas},
+    color::Color,
+    light::{Light, LightKind},
+    matrix::Matrix,
+    mesh::Mesh,
+    shader::{FragmentShader, Surface, Uniforms, VertexShader},
+    vector::Vector,
+    vertex::Vertex,
+};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowOptions {
+    /// 阴影贴图的宽和高
+    pub size: u32,
+    /// 深度偏移, 单位是阴影贴图一个像素在该深度处的宽度. 表面和光线夹角越小偏移越大
+    pub bias: f32,
+    /// PCF 半径, 比较周围 (2 * pcf + 1)² 个像素, 0 表示不做过滤
+    pub pcf: u32,
+}
+
+impl Default for ShadowOptions {
+    fn default() -> Self {
+        ShadowOptions {
+            size: 1024,
+            bias: 1.5,
+            pcf: 1,
+        }
+    }
+}
+
+pub struct ShadowMap {
+    size: u32,
+    /// 深度缓冲记录的是光源视线方向上的距离, 即裁剪空间的 w
+    depth: Vec<f32>,
+    /// 世界空间到光源裁剪空间
+    transform: Matrix,
+    /// 距离为 1 处一个像素的宽度
+    texel: f32,
+    bias: f32,
+    pcf: u32,
+}
+
+/// 深度数据太多, 不输出
+impl fmt::Debug for ShadowMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShadowMap")
+            .field("size", &self.size)
+            .field("transform", &self.transform)
+            .field("texel", &self.texel)
+            .field("bias", &self.bias)
+            .field("pcf", &self.pcf)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 只写深度的着色器
+struct DepthShader;
+
+impl VertexShader for DepthShader {
+    type Varying = f32;
+
+    fn vertex(&self, uniforms: &Uniforms, vertex: &Vertex) -> (Vector, f32) {
+        let position = uniforms.transform.transform_homogeneous(&vertex.position);
+        (position, 0.0)
+    }
+}
+
+impl FragmentShader<f32> for DepthShader {
+    fn fragment(&self, _: &Uniforms, _: &Surface, _: &f32) -> Color {
+        Color::new(255, 255, 255, 255)
+    }
+}
+
+impl ShadowMap {
+    /// 从光源的位置渲染 meshes 的深度. 视锥体包住所有模型的包围球.
+    /// 点光源在包围球里面时一张贴图无法覆盖所有方向, 只渲染朝向包围球中心约 160° 的范围,
+    /// 范围以外没有阴影. 没有顶点或场景全在聚光灯背后时返回 None
+    pub fn render(light: &Light, meshes: &[&Mesh], options: &ShadowOptions) -> Option<ShadowMap> {
+        let (center, radius) = bounding_sphere(meshes)?;
+        let camera = light_camera(light, &center, radius)?;
+
+        let size = options.size.max(1);
+        let mut canvas = Canvas::new(size, size);
+        canvas.set_camera(camera);
+        canvas.set_lights(vec![]);
+        canvas.clear();
+        for mesh in meshes {
+            canvas.draw_mesh_with(mesh, &DepthShader, &DepthShader);
+        }
+        let transform = camera.view_matrix() * camera.projection_matrix(1.0);
+        Some(ShadowMap {
+            size,
+            depth: canvas.framebuffer().depth_buffer().to_vec(),
+            transform,
+            // 画布只显示裁剪空间中 -0.5w 到 0.5w 的部分
+            texel: (camera.fov / 2.0).tan() / size as f32,
+            bias: options.bias,
+            pcf: options.pcf,
+        })
+    }
+
+    /// 世界空间中的点 point 被光照到的比例, 0 表示完全在阴影里.
+    /// cos 是法线和光线夹角的余弦, 用来按坡度放大深度偏移
+    pub fn visibility(&self, point: &Vector, cos: f32) -> f32 {
+        let clip = self.transform.transform_homogeneous(point);
+        if clip.w <= 0.0 {
+            return 1.0;
+        }
+        let size = self.size as f32;
+        let p = canvas::screen_position(&clip, size, size);
+        let cos = cos.clamp(0.0, 1.0);
+        let slope = ((1.0 - cos * cos).sqrt() / cos.max(1e-3)).min(10.0);
+        let depth = clip.w * (1.0 - self.bias * self.texel * (1.0 + slope));
+
+        let (x, y) = (p.x.floor() as i64, p.y.floor() as i64);
+        let r = self.pcf as i64;
+        let mut lit = 0;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let (x, y) = (x + dx, y + dy);
+                // 贴图范围以外没有遮挡
+                let inside = x >= 0 && y >= 0 && x < self.size as i64 && y < self.size as i64;
+                if !inside || depth <= self.depth[(y * self.size as i64 + x) as usize] {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / ((2 * r + 1) * (2 * r + 1)) as f32
+    }
+}
+
+/// 所有模型顶点在世界空间的包围球, 用顶点的平均位置作为球心
+fn bounding_sphere(meshes: &[&Mesh]) -> Option<(Vector, f32)> {
+    let points: Vec<Vector> = meshes
+        .iter()
+        .flat_map(|mesh| {
+            let world = mesh.world_matrix();
+            mesh.vertices
+                .iter()
+                .map(move |v| world.transform_homogeneous(&v.position))
+        })
+        .collect();
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f32;
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for p in &points {
+        x += p.x;
+        y += p.y;
+        z += p.z;
+    }
+    let center = Vector::new(x / n, y / n, z / n);
+    let radius = points
+        .iter()
+        .map(|p| (p - &center).length())
+        .fold(0.0, f32::max);
+    Some((center, radius.max(1e-3)))
+}
+
+/// 从光源看向包围球的相机, 近平面和远平面贴着包围球
+fn light_camera(light: &Light, center: &Vector, radius: f32) -> Option<Camera> {
+    let (position, target, half_angle) = match light.kind {
+        // 平行光用很远处视角很小的透视投影近似正交投影
+        LightKind::Directional { direction } => {
+            let d = direction.normalize();
+            let distance = radius * 20.0;
+            let position = Vector::new(
+                center.x - d.x * distance,
+                center.y - d.y * distance,
+                center.z - d.z * distance,
+            );
+            (position, *center, (radius / distance).asin())
+        }
+        LightKind::Point { position } => {
+            let distance = (center - &position).length();
+            if distance > radius {
+                (position, *center, (radius / distance).asin())
+            } else {
+                // 在包围球里面时退而求其次, 用很宽的视锥朝向中心, 近平面由下面的 znear 限制
+                let target = if distance > 0.0 {
+                    *center
+                } else {
+                    Vector::new(position.x, position.y, position.z + 1.0)
+                };
+                (position, target, 1.4)
+            }
+        }
+        LightKind::Spot {
+            position,
+            direction,
+            outer,
+            ..
+        } => {
+            let d = direction.normalize();
+            let target = Vector::new(position.x + d.x, position.y + d.y, position.z + d.z);
+            (position, target, outer.min(1.4))
+        }
+    };
+    let forward = (&target - &position).normalize();
+    let distance = forward.dot(&(center - &position));
+    if distance + radius <= 0.0 {
+        return None;
+    }
+    // 朝向接近竖直时换一个向上的方向, 否则 look_at_lh 无法确定坐标轴
+    let up = if forward.y.abs() > 0.99 {
+        Vector::new(0.0, 0.0, 1.0)
+    } else {
+        Vector::new(0.0, 1.0, 0.0)
+    };
+    Some(Camera {
+        position,
+        target,
+        up,
+        // 画布只显示裁剪空间 x, y 在 -0.5w 到 0.5w 之间的部分, 视角要放大才能包住整个半角
+        fov: 2.0 * (2.0 * half_angle.tan()).atan(),
+        znear: (distance - radius).max(radius * 0.01),
+        zfar: distance + radius,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::tests::quad;
+
+    #[test]
+    fn test_occluder() {
+        // 光源在正上方, 地面中心被上面的小方块挡住
+        let white = Color::new(255, 255, 255, 255);
+        let up = Vector::new(0.0, 1.0, 0.0);
+        let mut vertices = quad(&Vector::new(0.0, 0.0, 0.0), 5.0, up, 0.0, white);
+        vertices.extend(quad(&Vector::new(0.0, 2.0, 0.0), 1.0, up, 0.0, white));
+        let indices = vec![(0, 2, 1), (0, 3, 2), (4, 6, 5), (4, 7, 6)];
+        let mesh = Mesh::new(vertices, indices, None);
+
+        for light in [
+            Light::directional(Vector::new(0.0, -1.0, 0.0)),
+            Light::point(Vector::new(0.0, 10.0, 0.0)),
+            Light::spot(
+                Vector::new(0.0, 10.0, 0.0),
+                Vector::new(0.0, -1.0, 0.0),
+                0.5,
+                0.8,
+            ),
+        ] {
+            let map = ShadowMap::render(&light, &[&mesh], &ShadowOptions::default()).unwrap();
+            let at = |x, z| map.visibility(&Vector::new(x, 0.0, z), 1.0);
+            assert_eq!(at(0.0, 0.0), 0.0, "{:?}", light.kind);
+            // 没有自遮挡
+            assert_eq!(at(4.0, 0.0), 1.0, "{:?}", light.kind);
+            assert_eq!(at(0.0, -4.0), 1.0, "{:?}", light.kind);
+            // 遮挡物本身被照亮
+            assert_eq!(map.visibility(&Vector::new(0.5, 2.0, 0.5), 1.0), 1.0);
+        }
+        // 光源在包围球里面时只覆盖朝向中心的方向, 仍然有阴影
+        let inside = Light::point(Vector::new(3.0, 1.0, 0.0));
+        let map = ShadowMap::render(&inside, &[&mesh], &ShadowOptions::default()).unwrap();
+        assert_eq!(map.visibility(&Vector::new(-3.0, 3.0, 0.0), 1.0), 0.0);
+        let ground = Vector::new(-2.0, 0.0, 1.0);
+        let cos = (&Vector::new(3.0, 1.0, 0.0) - &ground).normalize().y;
+        assert_eq!(map.visibility(&ground, cos), 1.0);
+    }
+}
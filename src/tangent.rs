@@ -0,0 +1,140 @@
+//! 按 MikkTSpace 的约定生成切线.
+//!
+//! 每个三角形的切线指向 u 增大的方向, 副切线指向 v 增大的方向.
+//! 副切线方向不同的三角形 (比如镜像的 uv) 不共用顶点, 共用的顶点会复制一份.
+//! 顶点切线是相邻三角形切线投影到顶点切平面后按顶角加权的和, 副切线 = w * normal × tangent.
+use crate::{vector::Vector, vertex::Vertex};
+
+/// 生成 vertices 的切线, 需要时复制顶点并修改 indices
+pub fn generate(vertices: &mut Vec<Vertex>, indices: &mut [(usize, usize, usize)]) {
+    let faces: Vec<(Vector, f32)> = indices
+        .iter()
+        .map(|&(a, b, c)| face_tangent(&vertices[a], &vertices[b], &vertices[c]))
+        .collect();
+
+    // 顶点的副切线方向, 0 表示还没有三角形用到它
+    let mut signs = vec![0.0; vertices.len()];
+    let mut mirrors: Vec<Option<usize>> = vec![None; vertices.len()];
+    for (&(_, sign), triangle) in faces.iter().zip(indices.iter_mut()) {
+        let (a, b, c) = triangle;
+        for i in [a, b, c] {
+            if signs[*i] == 0.0 {
+                signs[*i] = sign;
+            } else if signs[*i] != sign {
+                *i = *mirrors[*i].get_or_insert_with(|| {
+                    vertices.push(vertices[*i]);
+                    signs.push(sign);
+                    vertices.len() - 1
+                });
+            }
+        }
+    }
+
+    let mut sums = vec![Vector::new(0.0, 0.0, 0.0); vertices.len()];
+    for (&(tangent, _), &(a, b, c)) in faces.iter().zip(indices.iter()) {
+        for (i, j, k) in [(a, b, c), (b, c, a), (c, a, b)] {
+            let n = vertices[i].normal.normalize();
+            let t = orthogonalize(&tangent, &n);
+            let p = vertices[i].position;
+            let e1 = (&vertices[j].position - &p).normalize();
+            let e2 = (&vertices[k].position - &p).normalize();
+            let angle = e1.dot(&e2).clamp(-1.0, 1.0).acos();
+            sums[i] = &sums[i] + &(&t * angle);
+        }
+    }
+
+    for ((v, sum), sign) in vertices.iter_mut().zip(sums).zip(signs) {
+        let n = v.normal.normalize();
+        let mut t = orthogonalize(&sum, &n);
+        // uv 退化时切线可以是切平面上任意方向
+        if t.length() == 0.0 {
+            t = any_perpendicular(&n);
+        }
+        t.w = if sign < 0.0 { -1.0 } else { 1.0 };
+        v.tangent = t;
+    }
+}
+
+/// 三角形的单位切线和副切线方向 (1 或 -1), uv 退化时切线为 0.
+/// 副切线方向由法线, 切线和 v 增大的方向决定, 和三角形的环绕方向无关
+fn face_tangent(a: &Vertex, b: &Vertex, c: &Vertex) -> (Vector, f32) {
+    let e1 = &b.position - &a.position;
+    let e2 = &c.position - &a.position;
+    let (du1, dv1) = (b.u - a.u, b.v - a.v);
+    let (du2, dv2) = (c.u - a.u, c.v - a.v);
+    let area = du1 * dv2 - du2 * dv1;
+    if area == 0.0 {
+        return (Vector::new(0.0, 0.0, 0.0), 1.0);
+    }
+    // 位置对 u 和 v 的偏导数
+    let t = &(&(&e1 * dv2) - &(&e2 * dv1)) * area.recip();
+    let bitangent = &(&(&e2 * du1) - &(&e1 * du2)) * area.recip();
+    let mut n = &(&a.normal + &b.normal) + &c.normal;
+    if n.length() == 0.0 {
+        n = e1.cross(&e2);
+    }
+    let sign = if n.cross(&t).dot(&bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    (Vector::new(t.x, t.y, t.z).normalize(), sign)
+}
+
+/// 去掉 v 在单位向量 n 上的分量后归一化
+fn orthogonalize(v: &Vector, n: &Vector) -> Vector {
+    let t = v - &(n * v.dot(n));
+    Vector::new(t.x, t.y, t.z).normalize()
+}
+
+fn any_perpendicular(n: &Vector) -> Vector {
+    let axis = if n.x.abs() < 0.9 {
+        Vector::new(1.0, 0.0, 0.0)
+    } else {
+        Vector::new(0.0, 1.0, 0.0)
+    };
+    orthogonalize(&axis, n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn test_generate() {
+        // 两个共用一条边的正方形, 右边的 uv 左右镜像
+        let white = Color::new(255, 255, 255, 255);
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let vertex =
+            |x: f32, y: f32, u: f32| Vertex::new(Vector::new(x, y, 0.0), normal, u, y, white);
+        let mut vertices = vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 1.0),
+            vertex(1.0, 1.0, 1.0),
+            vertex(0.0, 1.0, 0.0),
+            vertex(2.0, 0.0, 0.0),
+            vertex(2.0, 1.0, 0.0),
+        ];
+        let mut indices = vec![(0, 2, 1), (0, 3, 2), (1, 2, 5), (1, 5, 4)];
+        generate(&mut vertices, &mut indices);
+
+        // 中间的两个顶点被复制给右边的正方形
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(indices[2..], [(6, 7, 5), (6, 5, 4)]);
+        let tangent = |i: usize| {
+            let t = vertices[i].tangent;
+            (t.x.round(), t.y.round(), t.z.round(), t.w)
+        };
+        // 左手坐标系中法线朝 -z 时, 不镜像的一边副切线方向为 -1
+        assert_eq!(tangent(0), (1.0, 0.0, 0.0, -1.0));
+        assert_eq!(tangent(1), (1.0, 0.0, 0.0, -1.0));
+        assert_eq!(tangent(6), (-1.0, 0.0, 0.0, 1.0));
+        assert_eq!(tangent(4), (-1.0, 0.0, 0.0, 1.0));
+        // 副切线都指向 v 增大的方向
+        for i in [0, 4, 6] {
+            let b = vertices[i].bitangent();
+            assert!((b.y - 1.0).abs() < 1e-6, "{} {:?}", i, b);
+        }
+    }
+}
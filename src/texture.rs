@@ -1,7 +1,11 @@
-use crate::color::Color;
+use crate::{
+    color::Color,
+    image::{self, png, RgbaImage},
+};
 use anyhow::{anyhow, Result};
-use std::fs::File;
-use std::io::prelude::*;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
 
 pub struct Texture {
     pub pixels: Vec<Color>,
@@ -10,12 +14,18 @@ pub struct Texture {
 }
 
 impl Texture {
+    /// 读取 guaimage 贴图, 或者 png, bmp, tga, ppm 图片
     pub fn load(path: &str) -> Result<Self> {
-        let mut file = File::open(path)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        // println!("mesh\n{}", content);
-        Self::parse(content)
+        let content = fs::read(path)?;
+        if content.starts_with(b"guaimage") {
+            return Self::parse(String::from_utf8(content)?);
+        }
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let image = image::decode(&content, extension.as_deref())?;
+        Ok(Self::from_image(&image))
     }
 
     pub fn parse(content: String) -> Result<Self> {
@@ -32,7 +42,6 @@ impl Texture {
             .ok_or(anyhow!("贴图长度缺失"))?
             .trim()
             .parse()?;
-        println!("texture w {} h {}", width, height);
         let mut pixels = vec![];
         for _ in 0..height {
             let line = lines.next().ok_or(anyhow!("贴图数据行数不完整"))?.trim();
@@ -57,17 +66,66 @@ impl Texture {
         })
     }
 
-    pub fn sample(&self, mut u: f32, mut v: f32) -> Color {
-        if u > 1.0 {
-            u = 1.0
-        } else if u < 0.0 {
-            u = 0.0
+    /// 从上到下存放的图片转换成贴图, 行顺序翻转成从下往上
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let width = image.width as usize;
+        let height = image.height as usize;
+        let pixels = image
+            .pixels
+            .chunks(width.max(1) * 4)
+            .rev()
+            .flat_map(|row| row.chunks(4).map(|p| Color::new(p[0], p[1], p[2], p[3])))
+            .collect();
+        Texture {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    /// 按 RGBA 字节顺序从上到下导出像素.
+    /// 贴图数据是从下往上存放的 (v = 0 在底部), 这里翻转回正常的图片方向
+    pub fn to_rgba_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 4);
+        for row in self.pixels.chunks(self.width).rev() {
+            for Color { r, g, b, a } in row {
+                bytes.extend_from_slice(&[*r, *g, *b, *a]);
+            }
         }
-        if v > 1.0 {
-            v = 1.0
-        } else if v < 0.0 {
-            v = 0.0
+        bytes
+    }
+
+    /// guaimage 格式: 文件头, 版本, 宽, 高, 然后从下往上每行一个像素行, 像素是 RGBA 组成的 u32
+    pub fn to_guaimage(&self) -> String {
+        let mut content = format!("guaimage\n1.0\n{}\n{}", self.width, self.height);
+        for row in self.pixels.chunks(self.width.max(1)) {
+            content.push('\n');
+            for (i, c) in row.iter().enumerate() {
+                let pixel = u32::from_be_bytes([c.r, c.g, c.b, c.a]);
+                let separator = if i == 0 { "" } else { " " };
+                write!(content, "{}{}", separator, pixel).unwrap();
+            }
         }
+        content
+    }
+
+    pub fn save_guaimage(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_guaimage())?;
+        Ok(())
+    }
+
+    pub fn save_png(&self, path: &str) -> Result<()> {
+        png::save(
+            path,
+            self.width as u32,
+            self.height as u32,
+            &self.to_rgba_bytes(),
+        )
+    }
+
+    pub fn sample(&self, u: f32, v: f32) -> Color {
+        let u = u.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
 
         let w = self.width;
         let h = self.height;
@@ -86,7 +144,7 @@ mod tests {
 
     #[test]
     fn test_sample() {
-        let t = Texture::load("assets/illidan.image").unwrap();
+        let t = Texture::load("assets/illidan.guaimage").unwrap();
         let mut result = vec![];
         for i in 0..5 {
             let c = t.sample(i as f32 * 0.1, i as f32 * 0.1);
@@ -126,4 +184,12 @@ mod tests {
         ];
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_guaimage_round_trip() {
+        // 和原来的 Python 转换脚本生成的文件完全一致
+        let content = fs::read_to_string("assets/ahri.image").unwrap();
+        let t = Texture::parse(content.clone()).unwrap();
+        assert!(t.to_guaimage() == content);
+    }
 }
@@ -0,0 +1,165 @@
+//! 分块并行光栅化.
+//!
+//! 投影后的三角形先按覆盖到的行分到各个图块, 图块是若干整行组成的条带, 在内存中连续,
+//! 每个工作线程独占自己图块的颜色和深度缓冲. 图块内仍按提交顺序绘制, 每个像素经历的
+//! 深度测试和混合与单线程完全相同, 所以结果逐像素一致.
+use crate::{
+    color::Color,
+    framebuffer::{FrameBuffer, Tile},
+    rasterizer::{Fragment, Rect, Triangle},
+    vertex::Vertex,
+};
+use std::thread;
+
+/// 每个图块的行数
+pub const TILE_ROWS: u32 = 16;
+
+/// 准备好光栅化的三角形, 屏幕坐标在 triangle 中, vertices 是三个顶点要插值的数据
+#[derive(Copy, Clone, Debug)]
+pub struct Primitive<V = Vertex> {
+    pub triangle: Triangle,
+    pub vertices: [V; 3],
+    /// Mesh::materials 的下标
+    pub material: Option<usize>,
+}
+
+/// 按顺序绘制所有三角形, threads 不超过 1 时在当前线程里直接绘制.
+/// shade 根据三角形和片元计算颜色
+pub fn draw_primitives<V, F>(
+    framebuffer: &mut FrameBuffer,
+    primitives: &[Primitive<V>],
+    threads: usize,
+    shade: F,
+) where
+    V: Sync,
+    F: Fn(&Primitive<V>, &Fragment) -> Color + Sync,
+{
+    let screen = Rect::new(0, 0, framebuffer.width(), framebuffer.height());
+    if threads <= 1 {
+        for p in primitives {
+            p.triangle.rasterize(screen, |fragment| {
+                let color = shade(p, &fragment);
+                framebuffer.set_pixel(fragment.x, fragment.y, fragment.depth, color);
+            });
+        }
+        return;
+    }
+
+    let tiles = framebuffer.tiles_mut(TILE_ROWS);
+    let mut bins = vec![vec![]; tiles.len()];
+    for (i, p) in primitives.iter().enumerate() {
+        if let Some(bounds) = p.triangle.bounds(screen) {
+            let first = (bounds.y0 / TILE_ROWS) as usize;
+            let last = ((bounds.y1 - 1) / TILE_ROWS) as usize;
+            for bin in &mut bins[first..=last] {
+                bin.push(i);
+            }
+        }
+    }
+
+    // 图块交错分给各个线程, 模型集中在画面某一部分时负载也比较平均
+    let mut jobs: Vec<Vec<(Tile, Vec<usize>)>> = (0..threads).map(|_| vec![]).collect();
+    for (i, job) in tiles.into_iter().zip(bins).enumerate() {
+        jobs[i % threads].push(job);
+    }
+    let shade = &shade;
+    thread::scope(|scope| {
+        for job in jobs {
+            scope.spawn(move || {
+                for (mut tile, bin) in job {
+                    let rect = tile.rect();
+                    for i in bin {
+                        let p = &primitives[i];
+                        p.triangle.rasterize(rect, |fragment| {
+                            let color = shade(p, &fragment);
+                            tile.set_pixel(fragment.x, fragment.y, fragment.depth, color);
+                        });
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// 默认的工作线程数
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Vector;
+
+    #[test]
+    fn test_empty_framebuffer() {
+        let position = |x, y| Vector::new(x, y, 1.0);
+        let triangle = Triangle::new(
+            &position(0.0, 0.0),
+            &position(8.0, 0.0),
+            &position(0.0, 8.0),
+        );
+        let vertex = Vertex::new(
+            Vector::default(),
+            Vector::default(),
+            0.0,
+            0.0,
+            Color::default(),
+        );
+        let primitives = [Primitive {
+            triangle: triangle.unwrap(),
+            vertices: [vertex; 3],
+            material: None,
+        }];
+        for (width, height) in [(0, 16), (16, 0)] {
+            let mut framebuffer = FrameBuffer::new(width, height);
+            assert!(framebuffer.tiles_mut(4).is_empty());
+            draw_primitives(&mut framebuffer, &primitives, 4, |p, _| p.vertices[0].color);
+        }
+    }
+
+    #[test]
+    fn test_parallel_matches_single_thread() {
+        // 互相重叠的半透明三角形, 结果依赖绘制顺序
+        let mut primitives = vec![];
+        for i in 0..40 {
+            let f = i as f32;
+            let color = Color::new((i * 37 % 256) as u8, (i * 11) as u8, 200, 128);
+            let vertex = |x, y, z: f32| {
+                let position = Vector {
+                    x,
+                    y,
+                    z,
+                    w: 1.0 / z,
+                };
+                Vertex::new(position, Vector::default(), 0.0, 0.0, color)
+            };
+            let vertices = [
+                vertex(f * 3.0, f * 2.0, 5.0 + f),
+                vertex(120.0 - f, 10.0 + f * 4.0, 9.0),
+                vertex(f * 5.0 % 90.0, 150.0 - f, 3.0),
+            ];
+            let [a, b, c] = vertices.map(|v| v.position);
+            if let Some(triangle) = Triangle::new(&a, &b, &c) {
+                primitives.push(Primitive {
+                    triangle,
+                    vertices,
+                    material: None,
+                });
+            }
+        }
+        let shade = |p: &Primitive, _: &Fragment| p.vertices[0].color;
+        let render = |threads| {
+            let mut framebuffer = FrameBuffer::new(123, 141);
+            draw_primitives(&mut framebuffer, &primitives, threads, shade);
+            (
+                framebuffer.pixels().to_vec(),
+                framebuffer.depth_buffer().to_vec(),
+            )
+        };
+        let expected = render(1);
+        for threads in [2, 3, 8] {
+            assert!(render(threads) == expected);
+        }
+    }
+}
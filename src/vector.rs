@@ -1,4 +1,4 @@
-use std::ops::Sub;
+use std::ops::{Add, Mul, Sub};
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Vector {
@@ -43,6 +43,31 @@ impl Vector {
     }
 }
 
+impl Add for &Vector {
+    type Output = Vector;
+    fn add(self, rhs: Self) -> Self::Output {
+        Vector {
+            x: self.x + rhs.x,
+            y: self.y + rhs.y,
+            z: self.z + rhs.z,
+            w: self.w + rhs.w,
+        }
+    }
+}
+
+/// 各分量乘以 k, 包括 w
+impl Mul<f32> for &Vector {
+    type Output = Vector;
+    fn mul(self, k: f32) -> Self::Output {
+        Vector {
+            x: self.x * k,
+            y: self.y * k,
+            z: self.z * k,
+            w: self.w * k,
+        }
+    }
+}
+
 impl Sub for &Vector {
     type Output = Vector;
     fn sub(self, rhs: Self) -> Self::Output {
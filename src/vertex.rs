@@ -7,6 +7,8 @@ pub struct Vertex {
     pub v: f32,
     pub color: Color,
     pub intensity: f32,
+    /// 切线, 指向 u 增大的方向, w 是副切线的方向 (1 或 -1). 没有生成切线时为 0
+    pub tangent: Vector,
 }
 
 impl Vertex {
@@ -18,6 +20,13 @@ impl Vertex {
             v,
             color,
             intensity: 1.0,
+            tangent: Vector::default(),
         }
     }
+
+    /// 副切线, 指向 v 增大的方向
+    pub fn bitangent(&self) -> Vector {
+        let b = self.normal.cross(&self.tangent);
+        &b * self.tangent.w
+    }
 }
@@ -1,19 +1,42 @@
-use crate::{canvas::Canvas, mesh::Mesh};
+use crate::{
+    camera::Camera,
+    canvas::{Canvas, CullMode, FrontFace, ShadingModel},
+    light::Light,
+    mesh::Mesh,
+    shader::BlinnPhongShader,
+};
 use anyhow::{anyhow, Result};
-use sdl2::{event::Event, keyboard::Keycode, Sdl};
-pub struct Window<'a> {
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+    render::{self, Texture},
+    video, Sdl,
+};
+
+/// SDL 窗口, 只负责处理输入和显示 Canvas 的帧缓冲
+pub struct Window {
     sdl: Sdl,
-    canvas: Canvas<'a>,
+    renderer: render::Canvas<video::Window>,
+    canvas: Canvas,
     mesh: Option<Mesh>,
     running: bool,
 }
 
-impl<'a> Window<'a> {
+impl Window {
     pub fn new(title: &str, width: u32, height: u32) -> Result<Self> {
         let sdl = sdl2::init().map_err(|_| anyhow!("sdl init error"))?;
-        let canvas = Canvas::new(&sdl, title, width, height);
+        let video_subsystem = sdl.video().map_err(|e| anyhow!(e))?;
+        let window = video_subsystem
+            .window(title, width, height)
+            .position_centered()
+            .allow_highdpi()
+            .build()?;
+        let renderer = window.into_canvas().build()?;
+        let canvas = Canvas::new(width, height);
         Ok(Window {
             sdl,
+            renderer,
             canvas,
             mesh: None,
             running: true,
@@ -24,14 +47,46 @@ impl<'a> Window<'a> {
         self.mesh = Some(mesh);
     }
 
-    pub fn run(&mut self) {
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.canvas.set_camera(camera);
+    }
+
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.canvas.set_cull_mode(cull_mode);
+    }
+
+    pub fn set_front_face(&mut self, front_face: FrontFace) {
+        self.canvas.set_front_face(front_face);
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        self.canvas.lights()
+    }
+
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        self.canvas.set_lights(lights);
+    }
+
+    pub fn set_shading_model(&mut self, shading_model: ShadingModel) {
+        self.canvas.set_shading_model(shading_model);
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        // 窗口纹理只创建一次, 每帧把帧缓冲整体上传一次
+        let texture_creator = self.renderer.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            self.canvas.width(),
+            self.canvas.height(),
+        )?;
         while self.running {
             self.update_input();
             self.update();
             self.clear();
             self.draw();
-            self.show();
+            self.show(&mut texture)?;
         }
+        Ok(())
     }
 
     fn update(&mut self) {
@@ -43,11 +98,25 @@ impl<'a> Window<'a> {
             Some(mesh) => mesh,
             None => return,
         };
+        if self.canvas.casts_shadows() {
+            self.canvas.render_shadows(&[mesh]);
+        }
         self.canvas.draw_mesh(mesh);
     }
 
-    fn show(&mut self) {
-        self.canvas.render();
+    fn show(&mut self, texture: &mut Texture) -> Result<()> {
+        let framebuffer = self.canvas.framebuffer();
+        texture
+            .with_lock(None, |bytes, pitch| {
+                framebuffer.write_rgba_bytes(bytes, pitch)
+            })
+            .map_err(|e| anyhow!(e))?;
+        self.renderer.clear();
+        self.renderer
+            .copy(texture, None, None)
+            .map_err(|e| anyhow!(e))?;
+        self.renderer.present();
+        Ok(())
     }
 
     fn clear(&mut self) {
@@ -59,7 +128,24 @@ impl<'a> Window<'a> {
     }
 
     fn on_keydown_event(&mut self, key: Keycode) {
-        let mut mesh = match &mut self.mesh {
+        if key == Keycode::C {
+            let cull_mode = match self.canvas.cull_mode() {
+                CullMode::None => CullMode::Back,
+                CullMode::Back => CullMode::Front,
+                CullMode::Front => CullMode::None,
+            };
+            self.canvas.set_cull_mode(cull_mode);
+            return;
+        }
+        if key == Keycode::L {
+            let shading_model = match self.canvas.shading_model() {
+                ShadingModel::Gouraud => ShadingModel::BlinnPhong(BlinnPhongShader::default()),
+                ShadingModel::BlinnPhong(_) => ShadingModel::Gouraud,
+            };
+            self.canvas.set_shading_model(shading_model);
+            return;
+        }
+        let mesh = match &mut self.mesh {
             Some(mesh) => mesh,
             None => return,
         };
@@ -77,7 +163,7 @@ impl<'a> Window<'a> {
     }
 
     fn on_mouse_event(&mut self, y: i32) {
-        let mut mesh = match &mut self.mesh {
+        let mesh = match &mut self.mesh {
             Some(mesh) => mesh,
             None => return,
         };
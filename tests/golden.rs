@@ -0,0 +1,236 @@
+//! 光栅化回归测试: 用内置模型渲染固定场景, 与 tests/golden 中的参考图片逐像素比较.
+//! 不一致时把实际结果和差异图写到 target/tmp/golden 目录.
+//! 确认改动无误后, 用 `UPDATE_GOLDEN=1 cargo test --test golden` 重新生成参考图片.
+use renderer_rs::{
+    image::png, BlinnPhongShader, Camera, Canvas, Color, CullMode, Light, Mesh, ShadingModel,
+    ShadowOptions, Texture, Vector, Vertex,
+};
+use std::{env, f32::consts::PI, fs, path::PathBuf};
+
+const WIDTH: u32 = 200;
+const HEIGHT: u32 = 150;
+/// 每个通道允许的误差
+const CHANNEL_TOLERANCE: u8 = 2;
+/// 允许超出误差的像素个数
+const PIXEL_TOLERANCE: usize = 0;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name))
+}
+
+fn output_path(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn check(name: &str, canvas: &Canvas) {
+    let framebuffer = canvas.framebuffer();
+    let actual = framebuffer.to_rgba_bytes();
+    let golden = golden_path(name);
+    let golden = golden.to_str().unwrap();
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        framebuffer.save_png(golden).unwrap();
+        return;
+    }
+
+    let expected =
+        png::load(golden).unwrap_or_else(|e| panic!("读取参考图片 {} 失败: {}", golden, e));
+    assert_eq!(
+        (expected.width, expected.height),
+        (framebuffer.width(), framebuffer.height()),
+        "{} 尺寸不一致",
+        name
+    );
+
+    // 差异图: 超出误差的像素标红, 其余像素显示为变暗的参考图
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut mismatched = 0;
+    let mut max_error = 0;
+    for (a, e) in actual.chunks(4).zip(expected.pixels.chunks(4)) {
+        let error = a.iter().zip(e).map(|(a, e)| a.abs_diff(*e)).max().unwrap();
+        max_error = max_error.max(error);
+        if error > CHANNEL_TOLERANCE {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+    if mismatched > PIXEL_TOLERANCE {
+        let actual_path = output_path(&format!("{}.actual.png", name));
+        let diff_path = output_path(&format!("{}.diff.png", name));
+        framebuffer.save_png(actual_path.to_str().unwrap()).unwrap();
+        png::save(diff_path.to_str().unwrap(), WIDTH, HEIGHT, &diff).unwrap();
+        panic!(
+            "{}: {} 个像素超出误差, 最大误差 {}\n实际结果 {}\n差异图 {}",
+            name,
+            mismatched,
+            max_error,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn render(mesh: &mut Mesh) -> Canvas {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_threads(4);
+    canvas.clear();
+    canvas.draw_mesh(mesh);
+    canvas
+}
+
+fn ahri() -> Mesh {
+    let mut mesh = Mesh::load_obj("assets/ahri.obj", Some("assets/ahri.image")).unwrap();
+    mesh.set_position(Vector::new(0.0, 0.0, -10.0));
+    mesh.rotation = Vector::new(0.0, PI, 0.0);
+    mesh
+}
+
+fn illidan(texture: Option<&str>) -> Mesh {
+    let mut mesh = Mesh::load_gua3d("assets/illidan.gua3d", texture).unwrap();
+    mesh.set_position(Vector::new(0.0, -2.5, -10.0));
+    mesh.rotation = Vector::new(0.0, PI + 0.6, 0.0);
+    mesh
+}
+
+#[test]
+fn ahri_front() {
+    check("ahri_front", &render(&mut ahri()));
+}
+
+#[test]
+fn ahri_turned() {
+    let mut mesh = ahri();
+    mesh.rotation = Vector::new(0.3, 2.4, 0.0);
+    check("ahri_turned", &render(&mut mesh));
+}
+
+#[test]
+fn ahri_back_face_culling() {
+    // ahri 不是完全封闭的模型, 剔除背面后轮廓上个别像素会露出背景, 所以使用单独的参考图片
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_cull_mode(CullMode::Back);
+    canvas.clear();
+    canvas.draw_mesh(&mut ahri());
+    let stats = canvas.stats();
+    assert!(stats.culled * 3 > stats.triangles, "{:?}", stats);
+    check("ahri_back_face_culling", &canvas);
+}
+
+#[test]
+fn ahri_blinn_phong() {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_threads(4);
+    canvas.set_shading_model(ShadingModel::BlinnPhong(BlinnPhongShader::default()));
+    canvas.clear();
+    canvas.draw_mesh(&mut ahri());
+    check("ahri_blinn_phong", &canvas);
+}
+
+#[test]
+fn ahri_colored_lights() {
+    // 红色点光源, 从右上方照下来的蓝色聚光灯和微弱的平行光
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_threads(4);
+    canvas.set_shading_model(ShadingModel::BlinnPhong(BlinnPhongShader::default()));
+    canvas.set_lights(vec![
+        Light {
+            color: Vector::new(1.0, 0.3, 0.2),
+            ..Light::point(Vector::new(-4.0, 2.0, -15.0))
+        },
+        Light {
+            color: Vector::new(0.3, 0.5, 1.0),
+            intensity: 1.5,
+            ..Light::spot(
+                Vector::new(3.0, 6.0, -14.0),
+                Vector::new(-0.3, -0.6, 0.4),
+                0.2,
+                0.4,
+            )
+        },
+        Light {
+            intensity: 0.2,
+            ..Light::directional(Vector::new(0.0, 0.0, 1.0))
+        },
+    ]);
+    canvas.clear();
+    canvas.draw_mesh(&mut ahri());
+    check("ahri_colored_lights", &canvas);
+}
+
+#[test]
+fn ahri_shadow() {
+    // 斜上方的平行光, 模型的影子落在脚下的地面上
+    let mut ahri = ahri();
+    let (low, high) = ahri
+        .vertices
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(low, high), v| {
+            (low.min(v.position.y), high.max(v.position.y))
+        });
+    let size = high - low;
+    let gray = Color::new(180, 180, 180, 255);
+    let up = Vector::new(0.0, 1.0, 0.0);
+    let vertex = |x, z| Vertex::new(Vector::new(x, low, z), up, 0.0, 0.0, gray);
+    let vertices = vec![
+        vertex(-size, -size),
+        vertex(size, -size),
+        vertex(size, size),
+        vertex(-size, size),
+    ];
+    let mut ground = Mesh::new(vertices, vec![(0, 2, 1), (0, 3, 2)], None);
+    ground.set_position(ahri.position);
+
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_threads(4);
+    canvas.set_camera(Camera::new(
+        Vector::new(0.0, high + size, -25.0),
+        Vector::new(0.0, (low + high) / 2.0, -10.0),
+    ));
+    canvas.set_shading_model(ShadingModel::BlinnPhong(BlinnPhongShader::default()));
+    canvas.set_lights(vec![Light {
+        shadow: Some(ShadowOptions::default()),
+        ..Light::directional(Vector::new(-0.5, -1.0, 0.8))
+    }]);
+    canvas.render_shadows(&[&ahri, &ground]);
+    canvas.clear();
+    canvas.draw_mesh(&mut ground);
+    canvas.draw_mesh(&mut ahri);
+    check("ahri_shadow", &canvas);
+}
+
+#[test]
+fn ahri_single_thread() {
+    // 单线程和分块并行光栅化的结果一致
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.set_threads(1);
+    canvas.clear();
+    canvas.draw_mesh(&mut ahri());
+    check("ahri_front", &canvas);
+}
+
+#[test]
+fn illidan_textured() {
+    let mut mesh = illidan(Some("assets/illidan.guaimage"));
+    check("illidan_textured", &render(&mut mesh));
+}
+
+#[test]
+fn illidan_untextured() {
+    let mut mesh = illidan(None);
+    check("illidan_untextured", &render(&mut mesh));
+}
+
+#[test]
+fn texture_image() {
+    let texture = Texture::load("assets/illidan.guaimage").unwrap();
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    canvas.clear();
+    canvas.draw_image(&texture);
+    check("texture_image", &canvas);
+}